@@ -0,0 +1,204 @@
+//! Compares reading response frames through `FramedRead` and
+//! `ModbusDataCodec` with a hand-rolled `read_exact` of the length prefix
+//! and then the body, both decoding the same stream of known frames.
+//!
+//! Also compares decoding bincode responses with raw ones, and times
+//! sustained polling through the client over the in-memory mock forwarder at
+//! several initial read buffer capacities.
+//!
+//! Run with `cargo bench --bench read_path`. Allocations and bytes per frame
+//! are printed before the timings, since criterion doesn't count them, as is
+//! how the client's own request timings split between the network and the
+//! decode.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+use bytes::BytesMut;
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use futures::StreamExt;
+use modbus_forwarder_client_test::client::DEFAULT_READ_BUFFER_CAPACITY;
+use modbus_forwarder_client_test::codec::{ModbusDataCodec, ResponseEncoding, WireFormat};
+use modbus_forwarder_client_test::{mock, ModbusForwarderClient, RequestTimings, Transport};
+use tokio::io::AsyncReadExt;
+use tokio::runtime::Runtime;
+use tokio_modbus::Request;
+use tokio_util::codec::{Encoder, FramedRead};
+
+const FRAMES: usize = 1000;
+// A full-size holding register read
+const REGISTERS: u16 = 125;
+
+struct CountingAllocator;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+fn frames(format: WireFormat) -> Vec<u8> {
+    let mut codec = ModbusDataCodec::new(format);
+    let mut buffer = BytesMut::new();
+    for i in 0..FRAMES {
+        let registers = (0..REGISTERS).map(|r| r.wrapping_add(i as u16)).collect();
+        codec.encode(registers, &mut buffer).unwrap();
+    }
+    buffer.to_vec()
+}
+
+async fn framed(bytes: &[u8], format: WireFormat) -> usize {
+    let mut reader = FramedRead::new(bytes, ModbusDataCodec::new(format));
+    let mut registers = 0;
+    while let Some(frame) = reader.next().await {
+        registers += frame.unwrap().len();
+    }
+    registers
+}
+
+async fn manual(mut bytes: &[u8]) -> usize {
+    let mut body = Vec::new();
+    let mut registers = 0;
+    // The default wire format: a big-endian length, then a fixint bincode
+    // payload, which is what plain `bincode::deserialize` reads
+    while let Ok(length) = bytes.read_u64().await {
+        body.resize(length as usize, 0);
+        bytes.read_exact(&mut body).await.unwrap();
+        let frame: Vec<u16> = bincode::deserialize(&body).unwrap();
+        registers += frame.len();
+    }
+    registers
+}
+
+fn allocations_per_frame(runtime: &Runtime, read: impl std::future::Future<Output = usize>) -> f64 {
+    let before = ALLOCATIONS.load(Ordering::Relaxed);
+    let registers = runtime.block_on(read);
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed) - before;
+    assert_eq!(registers, FRAMES * usize::from(REGISTERS));
+    allocations as f64 / FRAMES as f64
+}
+
+fn read_path(c: &mut Criterion) {
+    let runtime = runtime();
+    let format = WireFormat::default();
+    let bytes = frames(format);
+
+    println!(
+        "allocations per frame: framed {:.2}, manual {:.2}",
+        allocations_per_frame(&runtime, framed(&bytes, format)),
+        allocations_per_frame(&runtime, manual(&bytes)),
+    );
+
+    let mut group = c.benchmark_group("read_path");
+    group.throughput(Throughput::Bytes(bytes.len() as u64));
+    group.bench_function("framed", |b| {
+        b.iter(|| runtime.block_on(framed(&bytes, format)))
+    });
+    group.bench_function("manual", |b| b.iter(|| runtime.block_on(manual(&bytes))));
+    group.finish();
+}
+
+// Throughput is per frame rather than per byte, since the point is how
+// much less a raw frame costs to move and decode than a bincode one
+fn response_encoding(c: &mut Criterion) {
+    let runtime = runtime();
+    let mut group = c.benchmark_group("response_encoding");
+    group.throughput(Throughput::Elements(FRAMES as u64));
+    for (name, responses) in [
+        ("bincode", ResponseEncoding::Bincode),
+        ("raw", ResponseEncoding::Raw),
+    ] {
+        let format = WireFormat {
+            responses,
+            ..Default::default()
+        };
+        let bytes = frames(format);
+        println!(
+            "{name}: {} bytes and {:.2} allocations per frame",
+            bytes.len() / FRAMES,
+            allocations_per_frame(&runtime, framed(&bytes, format)),
+        );
+        group.bench_function(name, |b| {
+            b.iter(|| runtime.block_on(framed(&bytes, format)))
+        });
+    }
+    group.finish();
+}
+
+fn runtime() -> Runtime {
+    tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap()
+}
+
+// Answers every holding register read in full, as a device would
+fn device(request: Request<'static>) -> Option<Vec<u16>> {
+    match request {
+        Request::ReadHoldingRegisters(addr, count) => {
+            Some((0..count).map(|i| addr.wrapping_add(i)).collect())
+        }
+        _ => None,
+    }
+}
+
+// Averages the client's timings over a run of full-size reads
+fn mean_timings<T: Transport>(
+    runtime: &Runtime,
+    client: &mut ModbusForwarderClient<T>,
+) -> RequestTimings {
+    let (mut network, mut decode) = (Duration::ZERO, Duration::ZERO);
+    for _ in 0..FRAMES {
+        runtime
+            .block_on(client.read_holding_registers(0, REGISTERS))
+            .unwrap();
+        let timings = client.last_timings().unwrap();
+        network += timings.network;
+        decode += timings.decode;
+    }
+    RequestTimings {
+        network: network / FRAMES as u32,
+        decode: decode / FRAMES as u32,
+    }
+}
+
+fn read_buffer_capacity(c: &mut Criterion) {
+    let runtime = runtime();
+    let mut group = c.benchmark_group("read_buffer_capacity");
+    group.throughput(Throughput::Elements(1));
+    for capacity in [512, DEFAULT_READ_BUFFER_CAPACITY, 64 * 1024] {
+        // The mock has to be spawned onto the runtime that drives it
+        let mut client = runtime.block_on(async {
+            ModbusForwarderClient::builder("mock")
+                .read_buffer_capacity(capacity)
+                .build(mock::spawn(device))
+        });
+        let timings = mean_timings(&runtime, &mut client);
+        println!(
+            "read buffer of {capacity}: network {:?} and decode {:?} per request",
+            timings.network, timings.decode
+        );
+        group.bench_function(capacity.to_string(), |b| {
+            b.iter(|| {
+                runtime
+                    .block_on(client.read_holding_registers(0, REGISTERS))
+                    .unwrap()
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, read_path, response_encoding, read_buffer_capacity);
+criterion_main!(benches);
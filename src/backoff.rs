@@ -0,0 +1,137 @@
+use std::str::FromStr;
+use std::time::Duration;
+
+use anyhow::anyhow;
+use rand::Rng;
+
+use crate::error::is_connection_error;
+
+/// Default spread applied to poll intervals and reconnect delays, as a
+/// percentage of the base delay.
+pub const DEFAULT_JITTER_PERCENT: u8 = 10;
+
+/// Shifts `base` by a random offset of up to `percent` percent either way so
+/// that many clients started together drift apart instead of moving in step.
+pub fn jittered(base: Duration, percent: u8) -> Duration {
+    if percent == 0 {
+        return base;
+    }
+    let spread = base.as_secs_f64() * f64::from(percent.min(100)) / 100.0;
+    let offset = rand::thread_rng().gen_range(-spread..=spread);
+    Duration::from_secs_f64((base.as_secs_f64() + offset).max(0.0))
+}
+
+/// Exponential backoff between reconnect attempts, with jitter.
+#[derive(Debug, Clone)]
+pub struct Backoff {
+    initial: Duration,
+    max: Duration,
+    current: Duration,
+    jitter_percent: u8,
+}
+
+impl Backoff {
+    pub fn new(initial: Duration, max: Duration, jitter_percent: u8) -> Self {
+        Self {
+            initial,
+            max,
+            current: initial,
+            jitter_percent,
+        }
+    }
+
+    /// Returns the delay before the next attempt and doubles the base delay
+    /// for the one after, up to the maximum.
+    pub fn next_delay(&mut self) -> Duration {
+        let delay = jittered(self.current, self.jitter_percent);
+        self.current = (self.current * 2).min(self.max);
+        delay
+    }
+
+    pub fn reset(&mut self) {
+        self.current = self.initial;
+    }
+}
+
+/// How persistently the client reconnects after losing its connection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ReconnectPolicy {
+    /// Give up as soon as the connection is lost.
+    #[default]
+    Never,
+    /// Make a single attempt, e.g. to ride out a forwarder restart.
+    Once,
+    /// Make up to this many attempts.
+    UpTo(u32),
+    /// Keep trying until a connection is made.
+    Forever,
+}
+
+impl ReconnectPolicy {
+    /// Attempts allowed per lost connection, `None` meaning unlimited.
+    pub fn max_attempts(self) -> Option<u32> {
+        match self {
+            ReconnectPolicy::Never => Some(0),
+            ReconnectPolicy::Once => Some(1),
+            ReconnectPolicy::UpTo(attempts) => Some(attempts),
+            ReconnectPolicy::Forever => None,
+        }
+    }
+}
+
+/// Which failed requests the client sends again, and how many times.
+///
+/// Nothing is retried by default. With attempts to spare, only errors the
+/// `retryable` predicate accepts are retried; the default predicate is
+/// [`is_connection_error`], which covers transient transport failures: I/O
+/// errors such as timeouts and resets, the forwarder closing the connection,
+/// and requests refused because the connection was already down. Exception
+/// responses, undecodable frames and rejected arguments point at a
+/// misconfiguration and are returned straight away.
+#[derive(Debug, Clone, Copy)]
+pub struct RetryPolicy {
+    pub attempts: u32,
+    pub retryable: fn(&anyhow::Error) -> bool,
+}
+
+impl RetryPolicy {
+    /// Up to `attempts` retries of transient transport errors.
+    pub fn new(attempts: u32) -> Self {
+        Self {
+            attempts,
+            retryable: is_connection_error,
+        }
+    }
+
+    /// Replaces the test for whether an error is worth retrying.
+    pub fn when(mut self, retryable: fn(&anyhow::Error) -> bool) -> Self {
+        self.retryable = retryable;
+        self
+    }
+
+    pub fn should_retry(&self, err: &anyhow::Error, retries: u32) -> bool {
+        retries < self.attempts && (self.retryable)(err)
+    }
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self::new(0)
+    }
+}
+
+impl FromStr for ReconnectPolicy {
+    type Err = anyhow::Error;
+
+    /// Parses `never`, `once`, `forever`, or an attempt count.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "never" => Ok(ReconnectPolicy::Never),
+            "once" => Ok(ReconnectPolicy::Once),
+            "forever" => Ok(ReconnectPolicy::Forever),
+            attempts => attempts.parse().map(ReconnectPolicy::UpTo).map_err(|_| {
+                anyhow!("expected never, once, forever or an attempt count, got {attempts:?}")
+            }),
+        }
+    }
+}
@@ -0,0 +1,68 @@
+use std::collections::HashMap;
+
+use anyhow::{anyhow, Result};
+
+use crate::decode::{self, FieldType, Order, RegisterMap, Value};
+
+/// A block of registers together with the address it was read from, so
+/// that values can be looked up by absolute address without panicking on
+/// addresses outside the block.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RegisterBlock {
+    start: u16,
+    data: Vec<u16>,
+}
+
+impl RegisterBlock {
+    pub fn new(start: u16, data: Vec<u16>) -> Self {
+        Self { start, data }
+    }
+
+    pub fn start(&self) -> u16 {
+        self.start
+    }
+
+    pub fn len(&self) -> usize {
+        self.data.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    pub fn as_slice(&self) -> &[u16] {
+        &self.data
+    }
+
+    pub fn into_inner(self) -> Vec<u16> {
+        self.data
+    }
+
+    /// The register at absolute `address`, if the block covers it.
+    pub fn get(&self, address: u16) -> Option<u16> {
+        self.range(address, 1).map(|registers| registers[0])
+    }
+
+    /// The `len` registers starting at absolute `address`, if the block
+    /// covers all of them.
+    pub fn range(&self, address: u16, len: usize) -> Option<&[u16]> {
+        let offset = usize::from(address.checked_sub(self.start)?);
+        self.data.get(offset..offset.checked_add(len)?)
+    }
+
+    /// Decodes a value of `field_type` starting at absolute `address`, if
+    /// the block covers every register it spans.
+    pub fn decode(&self, address: u16, field_type: FieldType, order: Order) -> Option<Value> {
+        self.range(address, field_type.width())
+            .map(|registers| decode::decode_field(registers, field_type, order))
+    }
+
+    /// Decodes every field of `map`, with the map's offsets counted from
+    /// absolute `address`.
+    pub fn decode_map(&self, address: u16, map: &RegisterMap) -> Result<HashMap<String, Value>> {
+        let registers = self
+            .range(address, map.register_count())
+            .ok_or_else(|| anyhow!("register map at {address} runs outside the block"))?;
+        decode::decode_map(registers, map)
+    }
+}
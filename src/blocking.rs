@@ -0,0 +1,60 @@
+// Synchronous wrapper around the async client for callers without a runtime.
+
+use anyhow::Result;
+use tokio::runtime::{Builder, Runtime};
+use tokio_modbus::Request;
+
+/// Blocking counterpart of [`crate::ModbusForwarderClient`].
+///
+/// Each client creates and owns a single-threaded tokio runtime and drives
+/// every call to completion on it with `block_on`. Don't use it from within
+/// an async context: blocking a runtime thread on another runtime panics.
+pub struct ModbusForwarderClient {
+    runtime: Runtime,
+    inner: crate::ModbusForwarderClient,
+}
+
+impl ModbusForwarderClient {
+    pub fn connect(addr: impl Into<String>) -> Result<Self> {
+        Self::from_builder(crate::ModbusForwarderClient::builder(addr))
+    }
+
+    /// Connects with a configured builder, for options beyond the defaults.
+    pub fn from_builder(builder: crate::ClientBuilder) -> Result<Self> {
+        let runtime = Builder::new_current_thread().enable_all().build()?;
+        let inner = runtime.block_on(builder.connect())?;
+        Ok(Self { runtime, inner })
+    }
+
+    pub fn request(&mut self, request: Request<'static>) -> Result<Vec<u16>> {
+        self.runtime.block_on(self.inner.request(request))
+    }
+
+    pub fn read_holding_registers(&mut self, addr: u16, count: u16) -> Result<Vec<u16>> {
+        self.runtime
+            .block_on(self.inner.read_holding_registers(addr, count))
+    }
+
+    pub fn read_input_registers(&mut self, addr: u16, count: u16) -> Result<Vec<u16>> {
+        self.runtime
+            .block_on(self.inner.read_input_registers(addr, count))
+    }
+
+    pub fn read_coils(&mut self, addr: u16, count: u16) -> Result<Vec<u16>> {
+        self.runtime.block_on(self.inner.read_coils(addr, count))
+    }
+
+    pub fn read_discrete_inputs(&mut self, addr: u16, count: u16) -> Result<Vec<u16>> {
+        self.runtime
+            .block_on(self.inner.read_discrete_inputs(addr, count))
+    }
+
+    pub fn write_multiple_registers(&mut self, addr: u16, values: &[u16]) -> Result<usize> {
+        self.runtime
+            .block_on(self.inner.write_multiple_registers(addr, values))
+    }
+
+    pub fn disconnect(self) -> Result<()> {
+        self.runtime.block_on(self.inner.disconnect())
+    }
+}
@@ -0,0 +1,1685 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::io;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant, SystemTime};
+
+use anyhow::{anyhow, bail, ensure, Result};
+use futures::{SinkExt, StreamExt};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpStream;
+use tokio::sync::broadcast;
+use tokio_modbus::Request;
+use tokio_util::codec::{FramedRead, FramedWrite};
+use tracing::{debug, info, info_span, warn, Instrument, Span};
+
+use crate::backoff::{Backoff, ReconnectPolicy, RetryPolicy, DEFAULT_JITTER_PERCENT};
+use crate::block::RegisterBlock;
+use crate::codec::{
+    Endian, IntEncoding, ModbusDataCodec, ModbusRequestCodec, ResponseEncoding, WireFormat,
+};
+use crate::custom::{
+    self, DeviceIdCategory, DeviceIdentification, ExceptionStatus, FileRecordRequest,
+};
+use crate::decode::{self, RegisterMap, Value};
+use crate::error::{
+    is_connection_error, relayed_exception, ByteCapReached, ConnectionClosed, NotConnected,
+    UndecodableFrame,
+};
+use crate::events::{ConnectionEvent, ConnectionState};
+use crate::outcome::RequestOutcome;
+use crate::proxy::Socks5Proxy;
+use crate::rate::RateLimiter;
+use crate::transport::{ProvidedStream, Transport};
+
+const DEFAULT_RECONNECT_INITIAL: Duration = Duration::from_millis(500);
+const DEFAULT_RECONNECT_MAX: Duration = Duration::from_secs(30);
+// Subscribers that fall further behind than this miss the oldest events
+const EVENT_CHANNEL_CAPACITY: usize = 16;
+
+/// Initial read buffer size, enough for a 125-register response many times
+/// over without the buffer having to grow under sustained polling.
+pub const DEFAULT_READ_BUFFER_CAPACITY: usize = 4 * 1024;
+
+/// Requests a batch keeps outstanding by default. Low enough that a long
+/// batch can't pile up in a small forwarder's receive buffer.
+pub const DEFAULT_MAX_IN_FLIGHT: usize = 8;
+
+// Bytes of the read buffer shown when a response is slow to arrive
+const HANG_DUMP_PREVIEW: usize = 64;
+
+/// Most registers the Modbus spec allows in one ReadHoldingRegisters request.
+pub const MAX_READ_REGISTERS: usize = 125;
+
+/// Most registers the Modbus spec allows in one WriteMultipleRegisters request.
+pub const MAX_WRITE_REGISTERS: usize = 123;
+
+const WRITE_SINGLE_COIL: u8 = 0x05;
+const WRITE_SINGLE_REGISTER: u8 = 0x06;
+const WRITE_MULTIPLE_REGISTERS: u8 = 0x10;
+
+type Reader<T> = FramedRead<<T as Transport>::Reader, ModbusDataCodec>;
+type Writer<T> = FramedWrite<<T as Transport>::Writer, ModbusRequestCodec>;
+
+/// Where the time of a single request went.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct RequestTimings {
+    /// From the request being flushed to its response being decoded, less
+    /// the decode itself: the forwarder, the device and the network.
+    pub network: Duration,
+    /// Deserializing the response payload locally.
+    pub decode: Duration,
+}
+
+/// What a connection ended up using, for logging and diagnostics.
+///
+/// The forwarder protocol has no handshake, so nothing here is negotiated;
+/// the wire format is the one the client was configured with and there is
+/// no protocol version to report.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConnectionInfo {
+    /// The forwarder address as configured.
+    pub addr: String,
+    /// The resolved address the transport is connected to. When tunnelling
+    /// this is the proxy; transports without addresses leave it `None`.
+    pub peer: Option<SocketAddr>,
+    pub wire_format: WireFormat,
+    /// Whether the connection goes through a SOCKS5 proxy.
+    pub proxied: bool,
+}
+
+/// High-level client for a modbus forwarder.
+///
+/// Every request is answered by exactly one data frame, so each method sends
+/// one request and waits for its response before returning.
+///
+/// The write side is always flushed before a response is awaited. Requests
+/// queued with `feed` but not yet flushed would otherwise sit in our buffer
+/// while we wait for an answer that can never come.
+///
+/// A connection error on either half tears down both: queued and partially
+/// read frames are discarded and the write side is shut down, so a response
+/// left over from the dead connection can never be mistaken for the answer
+/// to a later request. Until [`reconnect`](Self::reconnect) succeeds, every
+/// request fails with [`NotConnected`].
+///
+/// The client runs over any [`Transport`]; TCP is the default, and other
+/// transports are handed to [`ClientBuilder::build`].
+pub struct ModbusForwarderClient<T: Transport = TcpStream> {
+    addr: String,
+    reader: Reader<T>,
+    writer: Writer<T>,
+    reconnect_backoff: Backoff,
+    reconnect_policy: ReconnectPolicy,
+    retry_policy: RetryPolicy,
+    events: broadcast::Sender<ConnectionEvent>,
+    connected: bool,
+    split_writes: bool,
+    no_wait_writes: bool,
+    auto_chunk: bool,
+    read_buffer_capacity: usize,
+    max_in_flight: usize,
+    wire_format: WireFormat,
+    max_frame_len: Option<usize>,
+    proxy: Option<Socks5Proxy>,
+    hang_dump_after: Option<Duration>,
+    rate_limiter: Option<RateLimiter>,
+    max_bytes_read: Option<u64>,
+    // `Some` once recording is turned on
+    outcomes: Option<Vec<RequestOutcome>>,
+    // Read over earlier connections; the current codec counts its own
+    bytes_read_earlier: u64,
+    last_timings: Option<RequestTimings>,
+    info: ConnectionInfo,
+    span: Span,
+}
+
+/// Configures a [`ModbusForwarderClient`] before connecting.
+#[derive(Debug, Clone)]
+pub struct ClientBuilder {
+    addr: String,
+    jitter_percent: u8,
+    reconnect_initial: Duration,
+    reconnect_max: Duration,
+    reconnect_policy: ReconnectPolicy,
+    retry_policy: RetryPolicy,
+    events: broadcast::Sender<ConnectionEvent>,
+    split_writes: bool,
+    no_wait_writes: bool,
+    auto_chunk: bool,
+    read_buffer_capacity: usize,
+    max_in_flight: usize,
+    wire_format: WireFormat,
+    max_frame_len: Option<usize>,
+    proxy: Option<Socks5Proxy>,
+    hang_dump_after: Option<Duration>,
+    max_requests_per_second: Option<u32>,
+    max_bytes_read: Option<u64>,
+    record_outcomes: bool,
+}
+
+impl ClientBuilder {
+    /// Random spread applied to reconnect delays, as a percentage of the
+    /// base delay. Defaults to [`DEFAULT_JITTER_PERCENT`].
+    pub fn timeout_jitter(mut self, percent: u8) -> Self {
+        self.jitter_percent = percent;
+        self
+    }
+
+    /// First and largest delay between reconnect attempts.
+    pub fn reconnect_backoff(mut self, initial: Duration, max: Duration) -> Self {
+        self.reconnect_initial = initial;
+        self.reconnect_max = max;
+        self
+    }
+
+    /// How many attempts [`ModbusForwarderClient::reconnect`] makes after the
+    /// connection is lost. Defaults to [`ReconnectPolicy::Never`].
+    pub fn reconnect_policy(mut self, policy: ReconnectPolicy) -> Self {
+        self.reconnect_policy = policy;
+        self
+    }
+
+    /// Which failed requests [`ModbusForwarderClient::request`], and so every
+    /// read and write, sends again. Retrying after the connection was lost
+    /// reconnects first, which only succeeds if the reconnect policy allows
+    /// an attempt. Defaults to no retries.
+    pub fn retry_policy(mut self, policy: RetryPolicy) -> Self {
+        self.retry_policy = policy;
+        self
+    }
+
+    /// Split register writes longer than [`MAX_WRITE_REGISTERS`] into
+    /// several sequential requests instead of rejecting them. Off by default.
+    pub fn split_writes(mut self, split: bool) -> Self {
+        self.split_writes = split;
+        self
+    }
+
+    /// Send register writes without waiting for an acknowledgement, as needed
+    /// for broadcast writes the server never answers. Off by default.
+    ///
+    /// Without an acknowledgement there is no error detection: a write the
+    /// device rejects or never receives still reports success.
+    pub fn no_wait_writes(mut self, no_wait: bool) -> Self {
+        self.no_wait_writes = no_wait;
+        self
+    }
+
+    /// Split holding register reads longer than [`MAX_READ_REGISTERS`] into
+    /// several requests and concatenate the results. Off by default, in
+    /// which case the forwarder sees the read as asked for.
+    pub fn auto_chunk(mut self, auto_chunk: bool) -> Self {
+        self.auto_chunk = auto_chunk;
+        self
+    }
+
+    /// Initial capacity of the read buffer. Defaults to
+    /// [`DEFAULT_READ_BUFFER_CAPACITY`]; raise it if responses are routinely
+    /// larger so the buffer doesn't have to grow and shrink between reads.
+    pub fn read_buffer_capacity(mut self, capacity: usize) -> Self {
+        self.read_buffer_capacity = capacity;
+        self
+    }
+
+    /// Largest frame payload sent or accepted, in bytes. An oversized
+    /// request fails before anything is written, which catches a runaway
+    /// write before the forwarder rejects it less clearly. An oversized
+    /// response fails with a [`FrameTooLarge`](crate::error::FrameTooLarge)
+    /// as soon as its length prefix arrives, and the connection is dropped.
+    /// Unlimited by default.
+    pub fn max_frame_len(mut self, bytes: usize) -> Self {
+        self.max_frame_len = Some(bytes);
+        self
+    }
+
+    /// Most requests a batch sends before waiting for a response. Once that
+    /// many are unanswered, each further request waits for the oldest
+    /// response. Defaults to [`DEFAULT_MAX_IN_FLIGHT`]; zero counts as one.
+    pub fn max_in_flight(mut self, limit: usize) -> Self {
+        self.max_in_flight = limit.max(1);
+        self
+    }
+
+    /// Integer encoding of frame payloads, which has to match how the
+    /// forwarder was built. Defaults to [`IntEncoding::Fixint`].
+    ///
+    /// The forwarder protocol has no handshake to agree on this, so it is
+    /// configured per client rather than negotiated.
+    pub fn int_encoding(mut self, encoding: IntEncoding) -> Self {
+        self.wire_format.int_encoding = encoding;
+        self
+    }
+
+    /// Byte order of frame length prefixes, which has to match the
+    /// forwarder's. Defaults to [`Endian::Big`].
+    pub fn prefix_endian(mut self, endian: Endian) -> Self {
+        self.wire_format.prefix = endian;
+        self
+    }
+
+    /// Encoding of response payloads, which has to match the forwarder's.
+    /// Defaults to [`ResponseEncoding::Bincode`]; [`ResponseEncoding::Raw`]
+    /// is cheaper for forwarders that support it.
+    pub fn response_encoding(mut self, encoding: ResponseEncoding) -> Self {
+        self.wire_format.responses = encoding;
+        self
+    }
+
+    /// Reaches the forwarder through a SOCKS5 proxy, for connecting and for
+    /// reconnecting. Needs the `socks5` feature and a TCP transport.
+    pub fn proxy(mut self, proxy: Socks5Proxy) -> Self {
+        self.proxy = Some(proxy);
+        self
+    }
+
+    /// Logs what the read buffer holds each time a response has been
+    /// awaited this long, to tell a forwarder that sent nothing from one
+    /// that sent a partial frame. The wait itself carries on. Off by
+    /// default, and a zero duration turns it back off.
+    pub fn hang_dump_after(mut self, after: Duration) -> Self {
+        self.hang_dump_after = Some(after).filter(|after| !after.is_zero());
+        self
+    }
+
+    /// Caps how many requests per second the client sends, whatever drives
+    /// it: single requests, batches and polls all wait for their turn. This
+    /// trades throughput for not overloading a shared forwarder. Unlimited
+    /// by default, and zero turns the limit back off.
+    pub fn max_requests_per_second(mut self, limit: u32) -> Self {
+        self.max_requests_per_second = Some(limit).filter(|&limit| limit > 0);
+        self
+    }
+
+    /// Stops the session once this many bytes have been read from the
+    /// forwarder, counting whole frames across reconnects: the next request
+    /// disconnects instead and fails with
+    /// [`ByteCapReached`](crate::error::ByteCapReached). Meant as a guardrail
+    /// on metered links. Unlimited by default.
+    pub fn max_bytes_read(mut self, bytes: u64) -> Self {
+        self.max_bytes_read = Some(bytes);
+        self
+    }
+
+    /// Keeps a [`RequestOutcome`] for every request that waits for a
+    /// response, single or batched, until
+    /// [`ModbusForwarderClient::take_outcomes`] collects them. Off by
+    /// default, since the records grow without bound.
+    pub fn record_outcomes(mut self, record: bool) -> Self {
+        self.record_outcomes = record;
+        self
+    }
+
+    /// Subscribes to connection events before connecting, so the initial
+    /// `Connecting` and `Connected` events are not missed.
+    pub fn subscribe(&self) -> broadcast::Receiver<ConnectionEvent> {
+        self.events.subscribe()
+    }
+
+    /// Connects to the forwarder over TCP.
+    pub async fn connect(self) -> Result<ModbusForwarderClient> {
+        self.connect_transport().await
+    }
+
+    /// Connects to the forwarder with [`Transport::connect`].
+    pub async fn connect_transport<T: Transport>(self) -> Result<ModbusForwarderClient<T>> {
+        send_event(&self.events, &self.addr, ConnectionState::Connecting);
+        match open::<T>(&self.addr, self.proxy.as_ref()).await {
+            Ok(transport) => Ok(self.build(transport)),
+            Err(err) => {
+                send_event(&self.events, &self.addr, ConnectionState::Disconnected);
+                Err(err.into())
+            }
+        }
+    }
+
+    /// Builds a client over an already established transport. The builder's
+    /// address is then only used for logging and for reconnecting.
+    pub fn build<T: Transport>(self, transport: T) -> ModbusForwarderClient<T> {
+        let info = ConnectionInfo {
+            addr: self.addr.clone(),
+            peer: transport.peer_addr(),
+            wire_format: self.wire_format,
+            proxied: self.proxy.is_some(),
+        };
+        let (reader, writer) = frame(
+            transport,
+            self.read_buffer_capacity,
+            self.wire_format,
+            self.max_frame_len,
+        );
+        send_event(&self.events, &self.addr, ConnectionState::Connected);
+        let span = info_span!("connection", peer = %self.addr);
+        span.in_scope(|| log_connected(&info, "Connected"));
+        ModbusForwarderClient {
+            addr: self.addr,
+            reader,
+            writer,
+            reconnect_backoff: Backoff::new(
+                self.reconnect_initial,
+                self.reconnect_max,
+                self.jitter_percent,
+            ),
+            reconnect_policy: self.reconnect_policy,
+            retry_policy: self.retry_policy,
+            events: self.events,
+            connected: true,
+            split_writes: self.split_writes,
+            no_wait_writes: self.no_wait_writes,
+            auto_chunk: self.auto_chunk,
+            read_buffer_capacity: self.read_buffer_capacity,
+            max_in_flight: self.max_in_flight,
+            wire_format: self.wire_format,
+            max_frame_len: self.max_frame_len,
+            proxy: self.proxy,
+            hang_dump_after: self.hang_dump_after,
+            rate_limiter: self.max_requests_per_second.map(RateLimiter::new),
+            max_bytes_read: self.max_bytes_read,
+            outcomes: self.record_outcomes.then(Vec::new),
+            bytes_read_earlier: 0,
+            last_timings: None,
+            info,
+            span,
+        }
+    }
+}
+
+fn log_connected(info: &ConnectionInfo, message: &str) {
+    info!(
+        peer = ?info.peer,
+        wire_format = ?info.wire_format,
+        proxied = info.proxied,
+        "{message}"
+    );
+}
+
+// Opens a transport to `addr`, through `proxy` if there is one.
+async fn open<T: Transport>(addr: &str, proxy: Option<&Socks5Proxy>) -> io::Result<T> {
+    match proxy {
+        Some(proxy) => T::connect_via(proxy, addr).await,
+        None => T::connect(addr).await,
+    }
+}
+
+fn send_event(events: &broadcast::Sender<ConnectionEvent>, peer: &str, state: ConnectionState) {
+    // Sending only fails when nobody is subscribed, which is fine
+    let _ = events.send(ConnectionEvent {
+        state,
+        peer: peer.to_owned(),
+        at: SystemTime::now(),
+    });
+}
+
+// A zero-count read is meaningless and the forwarder's answer to one is
+// confusing, so it is refused before anything is sent.
+pub(crate) fn check_count(count: u16) -> Result<()> {
+    ensure!(count > 0, "count must be at least 1");
+    Ok(())
+}
+
+// The reader can't go on past an undecodable frame, so one of those ends the
+// connection as surely as a connection error does.
+fn breaks_connection(err: &anyhow::Error) -> bool {
+    is_connection_error(err) || err.downcast_ref::<UndecodableFrame>().is_some()
+}
+
+// An exception is two elements as well, so a true echo is checked for
+// before one.
+fn check_register_echo(addr: u16, value: u16, echo: &[u16]) -> Result<()> {
+    if echo == [addr, value] {
+        return Ok(());
+    }
+    if let Some(exception) = relayed_exception(WRITE_SINGLE_REGISTER, echo) {
+        return Err(exception.into());
+    }
+    bail!("write of {value} to {addr} was acknowledged as {echo:?}")
+}
+
+fn check_coil_echo(addr: u16, value: bool, echo: &[u16]) -> Result<()> {
+    let [echo_addr, echo_state] = echo[..] else {
+        bail!(
+            "coil write acknowledgement has {} registers, expected 2",
+            echo.len()
+        );
+    };
+    // On is 0xFF00 in a Modbus echo; anything non-zero counts so that a
+    // forwarder passing it on as 1 is accepted too
+    let echo_value = echo_state != 0;
+    // As for registers, a true echo is checked for before an exception
+    if echo_addr == addr && echo_value == value {
+        return Ok(());
+    }
+    if let Some(exception) = relayed_exception(WRITE_SINGLE_COIL, echo) {
+        return Err(exception.into());
+    }
+    bail!("coil write of {value} to {addr} was acknowledged as {echo_value} to {echo_addr}")
+}
+
+// The acknowledgement echoes the start address and how many registers were
+// written. As for single writes, a true echo is checked for before an
+// exception.
+fn check_multiple_echo(start: u16, quantity: usize, echo: &[u16]) -> Result<()> {
+    if echo.len() == 2 && echo[0] == start && usize::from(echo[1]) == quantity {
+        return Ok(());
+    }
+    if let Some(exception) = relayed_exception(WRITE_MULTIPLE_REGISTERS, echo) {
+        return Err(exception.into());
+    }
+    bail!("write of {quantity} registers at {start} was acknowledged as {echo:?}")
+}
+
+fn frame<T: Transport>(
+    transport: T,
+    read_buffer_capacity: usize,
+    format: WireFormat,
+    max_frame_len: Option<usize>,
+) -> (Reader<T>, Writer<T>) {
+    let (reader, writer) = transport.into_split();
+    let mut data_codec = ModbusDataCodec::new(format);
+    let mut request_codec = ModbusRequestCodec::new(format);
+    if let Some(max) = max_frame_len {
+        data_codec = data_codec.with_max_frame_len(max);
+        request_codec = request_codec.with_max_frame_len(max);
+    }
+    (
+        FramedRead::with_capacity(reader, data_codec, read_buffer_capacity),
+        FramedWrite::new(writer, request_codec),
+    )
+}
+
+impl ModbusForwarderClient {
+    pub fn builder(addr: impl Into<String>) -> ClientBuilder {
+        ClientBuilder {
+            addr: addr.into(),
+            jitter_percent: DEFAULT_JITTER_PERCENT,
+            reconnect_initial: DEFAULT_RECONNECT_INITIAL,
+            reconnect_max: DEFAULT_RECONNECT_MAX,
+            reconnect_policy: ReconnectPolicy::default(),
+            retry_policy: RetryPolicy::default(),
+            events: broadcast::channel(EVENT_CHANNEL_CAPACITY).0,
+            split_writes: false,
+            no_wait_writes: false,
+            auto_chunk: false,
+            read_buffer_capacity: DEFAULT_READ_BUFFER_CAPACITY,
+            max_in_flight: DEFAULT_MAX_IN_FLIGHT,
+            wire_format: WireFormat::default(),
+            max_frame_len: None,
+            proxy: None,
+            hang_dump_after: None,
+            max_requests_per_second: None,
+            max_bytes_read: None,
+            record_outcomes: false,
+        }
+    }
+
+    pub async fn connect(addr: impl Into<String>) -> Result<Self> {
+        Self::builder(addr).connect().await
+    }
+
+    /// Runs the client over a stream the caller has already connected, with
+    /// the default settings. To configure it, hand a [`ProvidedStream`] to
+    /// [`ClientBuilder::build`] instead.
+    pub fn from_stream<S>(stream: S) -> ModbusForwarderClient<ProvidedStream<S>>
+    where
+        S: AsyncRead + AsyncWrite + Send + Unpin + 'static,
+    {
+        Self::builder("stream").build(ProvidedStream(stream))
+    }
+}
+
+impl<T: Transport> ModbusForwarderClient<T> {
+    /// The tracing span carrying this connection's peer address. Instrument
+    /// work done on behalf of the connection with it so that log lines from
+    /// several clients stay attributable.
+    pub fn span(&self) -> &Span {
+        &self.span
+    }
+
+    /// Details of the current connection, or of the last one while
+    /// disconnected.
+    pub fn connection_info(&self) -> &ConnectionInfo {
+        &self.info
+    }
+
+    /// Timings of the last successful [`request`](Self::request), which
+    /// also backs the read and write methods. Batches overlap their
+    /// requests, so they don't record timings.
+    pub fn last_timings(&self) -> Option<RequestTimings> {
+        self.last_timings
+    }
+
+    /// Bytes received but not yet decoded into a response.
+    pub fn read_buffer(&self) -> &[u8] {
+        self.reader.read_buffer()
+    }
+
+    /// Subscribes to connection lifecycle events.
+    pub fn subscribe(&self) -> broadcast::Receiver<ConnectionEvent> {
+        self.events.subscribe()
+    }
+
+    fn emit(&self, state: ConnectionState) {
+        send_event(&self.events, &self.addr, state);
+    }
+
+    /// Hands over the outcomes recorded since the last call, oldest first.
+    /// Empty unless the client was built with
+    /// [`ClientBuilder::record_outcomes`].
+    pub fn take_outcomes(&mut self) -> Vec<RequestOutcome> {
+        self.outcomes
+            .as_mut()
+            .map(std::mem::take)
+            .unwrap_or_default()
+    }
+
+    /// Bytes read from the forwarder this session, as whole frames.
+    pub fn bytes_read(&self) -> u64 {
+        self.bytes_read_earlier + self.reader.decoder().bytes_decoded()
+    }
+
+    // Refuses to send once the byte cap is used up, disconnecting the first
+    // time so the forwarder isn't left waiting.
+    async fn check_byte_cap(&mut self) -> Result<()> {
+        let read = self.bytes_read();
+        let Some(cap) = self.max_bytes_read.filter(|&cap| read >= cap) else {
+            return Ok(());
+        };
+        if self.connected {
+            info!(read, cap, "Byte cap reached, disconnecting");
+            // Best effort, as in `tear_down`
+            let _ = self.writer.send(Request::Disconnect).await;
+            self.tear_down().await;
+        }
+        Err(ByteCapReached { cap, read }.into())
+    }
+
+    fn ensure_connected(&self) -> Result<()> {
+        if self.connected {
+            Ok(())
+        } else {
+            Err(NotConnected.into())
+        }
+    }
+
+    // Takes both halves out of service once either has failed. The halves
+    // themselves are only replaced on reconnect.
+    async fn tear_down(&mut self) {
+        if !self.connected {
+            return;
+        }
+        self.connected = false;
+        self.writer.write_buffer_mut().clear();
+        self.reader.read_buffer_mut().clear();
+        // Best effort: the write side may already be gone
+        let _ = self.writer.close().await;
+        self.emit(ConnectionState::Disconnected);
+        self.span.in_scope(|| info!("Disconnected"));
+    }
+
+    /// Drops the current connection and tries to open a new one as many
+    /// times as the reconnect policy allows, waiting a jittered exponential
+    /// backoff between attempts. Fails once the attempts are used up.
+    pub async fn reconnect(&mut self) -> Result<()> {
+        let span = self.span.clone();
+        async {
+            self.tear_down().await;
+            let max_attempts = self.reconnect_policy.max_attempts();
+            let mut attempts = 0;
+            loop {
+                if max_attempts.is_some_and(|max| attempts >= max) {
+                    bail!(
+                        "gave up reconnecting after {attempts} attempts ({:?} policy)",
+                        self.reconnect_policy
+                    );
+                }
+                attempts += 1;
+                let delay = self.reconnect_backoff.next_delay();
+                info!("Reconnecting in {delay:?}");
+                tokio::time::sleep(delay).await;
+                self.emit(ConnectionState::Reconnecting);
+                match open::<T>(&self.addr, self.proxy.as_ref()).await {
+                    Ok(transport) => {
+                        self.info.peer = transport.peer_addr();
+                        self.bytes_read_earlier += self.reader.decoder().bytes_decoded();
+                        let (reader, writer) = frame(
+                            transport,
+                            self.read_buffer_capacity,
+                            self.wire_format,
+                            self.max_frame_len,
+                        );
+                        self.reader = reader;
+                        self.writer = writer;
+                        self.reconnect_backoff.reset();
+                        self.connected = true;
+                        self.emit(ConnectionState::Connected);
+                        log_connected(&self.info, "Reconnected");
+                        return Ok(());
+                    }
+                    Err(err) => warn!("Reconnect attempt {attempts} failed: {err:#}"),
+                }
+            }
+        }
+        .instrument(span)
+        .await
+    }
+
+    /// Sends a raw request and returns the forwarder's response data,
+    /// retrying per the client's [`RetryPolicy`].
+    pub async fn request(&mut self, request: Request<'static>) -> Result<Vec<u16>> {
+        let span = self.span.clone();
+        async {
+            let started = Instant::now();
+            let result = self.request_with_retries(&request).await;
+            if let Some(outcomes) = &mut self.outcomes {
+                outcomes.push(RequestOutcome::new(
+                    request,
+                    &result,
+                    Some(started.elapsed()),
+                ));
+            }
+            result
+        }
+        .instrument(span)
+        .await
+    }
+
+    // Amends the outcome `request` just recorded once its response has been
+    // parsed, so a response that turns out to be an exception or nonsense is
+    // recorded as the failure it is.
+    fn parsed<U>(&mut self, parsed: Result<U>) -> Result<U> {
+        let last = self
+            .outcomes
+            .as_mut()
+            .and_then(|outcomes| outcomes.last_mut());
+        if let (Err(err), Some(outcome)) = (&parsed, last) {
+            outcome.fail(err);
+        }
+        parsed
+    }
+
+    async fn request_with_retries(&mut self, request: &Request<'static>) -> Result<Vec<u16>> {
+        let mut retries = 0;
+        loop {
+            let err = match self.request_once(request.clone()).await {
+                Ok(data) => return Ok(data),
+                Err(err) => err,
+            };
+            if !self.retry_policy.should_retry(&err, retries) {
+                return Err(err);
+            }
+            retries += 1;
+            warn!("Retrying request (retry {retries}) after: {err:#}");
+            if !self.connected {
+                if let Err(reconnect_err) = self.reconnect().await {
+                    // The original failure says more than the reconnect one
+                    warn!("Could not reconnect to retry: {reconnect_err:#}");
+                    return Err(err);
+                }
+            }
+        }
+    }
+
+    async fn request_once(&mut self, request: Request<'static>) -> Result<Vec<u16>> {
+        self.check_byte_cap().await?;
+        self.ensure_connected()?;
+        debug!(?request, "Sending request");
+        let result = self.exchange(request).await;
+        match &result {
+            Ok(data) => debug!(
+                registers = data.len(),
+                timings = ?self.last_timings,
+                "Received response"
+            ),
+            Err(err) => {
+                debug!("Request failed: {err:#}");
+                if breaks_connection(err) {
+                    self.tear_down().await;
+                }
+            }
+        }
+        result
+    }
+
+    /// Sends a request without waiting for a response, for requests the
+    /// server never answers such as broadcast writes. The frame is flushed
+    /// before returning, but nothing confirms the device acted on it.
+    pub async fn send_no_wait(&mut self, request: Request<'static>) -> Result<()> {
+        let span = self.span.clone();
+        async {
+            self.check_byte_cap().await?;
+            self.ensure_connected()?;
+            debug!(?request, "Sending request without waiting for a response");
+            // `send` flushes, so the frame has left our buffer once it returns
+            let result = match self.throttle().await {
+                Ok(()) => self.writer.send(request).await,
+                Err(err) => Err(err),
+            };
+            if matches!(&result, Err(err) if is_connection_error(err)) {
+                self.tear_down().await;
+            }
+            result
+        }
+        .instrument(span)
+        .await
+    }
+
+    /// Sends `requests` back to back, keeping up to
+    /// [`ClientBuilder::max_in_flight`] unanswered, and returns one result
+    /// per request, in request order: the forwarder answers in order, so
+    /// result `i` always belongs to request `i`.
+    ///
+    /// Exception responses are data like any other. If the connection fails
+    /// part way, or a response can't be decoded, which stops the reader, the
+    /// responses already received are kept, the request it failed on carries
+    /// the error, and every later one fails with [`NotConnected`].
+    pub async fn send_batch(&mut self, requests: Vec<Request<'static>>) -> Vec<Result<Vec<u16>>> {
+        let span = self.span.clone();
+        async {
+            let count = requests.len();
+            let recorded = self.outcomes.is_some().then(|| requests.clone());
+            let mut responses = Vec::with_capacity(count);
+            if let Err(err) = self.exchange_all(requests, &mut responses).await {
+                let failed_at = responses.len();
+                debug!("Batch failed at request {failed_at}: {err:#}");
+                // Whatever went wrong, responses to the requests still in
+                // flight can no longer be matched up
+                self.tear_down().await;
+                responses.push(Err(err));
+                responses.extend((failed_at + 1..count).map(|_| {
+                    Err(anyhow::Error::new(NotConnected)
+                        .context(format!("batch failed at request {failed_at}")))
+                }));
+            }
+            if let (Some(outcomes), Some(requests)) = (&mut self.outcomes, recorded) {
+                outcomes.extend(
+                    requests
+                        .into_iter()
+                        .zip(&responses)
+                        .map(|(request, result)| RequestOutcome::new(request, result, None)),
+                );
+            }
+            responses
+        }
+        .instrument(span)
+        .await
+    }
+
+    async fn exchange_all(
+        &mut self,
+        requests: Vec<Request<'static>>,
+        responses: &mut Vec<Result<Vec<u16>>>,
+    ) -> Result<()> {
+        self.check_byte_cap().await?;
+        self.ensure_connected()?;
+        let count = requests.len();
+        let mut requests = requests.into_iter();
+        let mut in_flight = 0;
+        while responses.len() < count {
+            // Top the window up before waiting on its oldest response
+            while in_flight < self.max_in_flight {
+                let Some(request) = requests.next() else {
+                    break;
+                };
+                debug!(?request, "Queueing request");
+                self.throttle().await?;
+                self.writer.feed(request).await?;
+                in_flight += 1;
+            }
+            self.writer.flush().await?;
+            debug_assert!(
+                self.writer.write_buffer().is_empty(),
+                "write buffer not empty while awaiting a response"
+            );
+
+            match self.next_frame().await {
+                Some(data) => responses.push(Ok(data?)),
+                None => return Err(ConnectionClosed.into()),
+            }
+            in_flight -= 1;
+        }
+        debug!(responses = count, "Received batch responses");
+        Ok(())
+    }
+
+    // Waits for the next frame, dumping the read buffer whenever the wait
+    // passes another `hang_dump_after`.
+    async fn next_frame(&mut self) -> Option<Result<Vec<u16>>> {
+        let frame = self.wait_for_frame().await?;
+        // Anything the reader fails with that isn't I/O is down to the frame,
+        // a frame too large to read at all included
+        Some(frame.map_err(|err| {
+            if err.is::<io::Error>() {
+                err
+            } else {
+                err.context(UndecodableFrame)
+            }
+        }))
+    }
+
+    async fn wait_for_frame(&mut self) -> Option<Result<Vec<u16>>> {
+        let Some(every) = self.hang_dump_after else {
+            return self.reader.next().await;
+        };
+        let mut waited = Duration::ZERO;
+        loop {
+            // `next` is cancel safe: a partial frame stays in the buffer
+            match tokio::time::timeout(every, self.reader.next()).await {
+                Ok(frame) => return frame,
+                Err(_) => {
+                    waited += every;
+                    self.dump_read_buffer(waited);
+                }
+            }
+        }
+    }
+
+    fn dump_read_buffer(&self, waited: Duration) {
+        let buffer = self.reader.read_buffer();
+        let preview = buffer[..buffer.len().min(HANG_DUMP_PREVIEW)]
+            .iter()
+            .map(|byte| format!("{byte:02x}"))
+            .collect::<Vec<_>>()
+            .join(" ");
+        let declared = self.wire_format.declared_length(buffer);
+        warn!(
+            buffered = buffer.len(),
+            ?declared,
+            "No complete response after {waited:?}; read buffer starts [{preview}]"
+        );
+    }
+
+    // Waits until the rate limit allows another request. Anything already
+    // queued is flushed first rather than held back for the wait.
+    async fn throttle(&mut self) -> Result<()> {
+        if let Some(wait) = self.rate_limiter.as_mut().and_then(RateLimiter::reserve) {
+            self.writer.flush().await?;
+            debug!("Rate limited for {wait:?}");
+            tokio::time::sleep(wait).await;
+        }
+        Ok(())
+    }
+
+    async fn exchange(&mut self, request: Request<'static>) -> Result<Vec<u16>> {
+        self.throttle().await?;
+        self.writer.feed(request).await?;
+        // Anything still buffered when we start waiting would never reach the
+        // forwarder, and neither would its response reach us
+        self.writer.flush().await?;
+        debug_assert!(
+            self.writer.write_buffer().is_empty(),
+            "write buffer not empty while awaiting a response"
+        );
+        let sent = Instant::now();
+        let data = match self.next_frame().await {
+            Some(data) => data?,
+            None => return Err(ConnectionClosed.into()),
+        };
+        let decode = self.reader.decoder().last_decode_time();
+        self.last_timings = Some(RequestTimings {
+            network: sent.elapsed().saturating_sub(decode),
+            decode,
+        });
+        Ok(data)
+    }
+
+    /// Reads `count` holding registers starting at `addr`.
+    ///
+    /// With [`ClientBuilder::auto_chunk`], counts above [`MAX_READ_REGISTERS`]
+    /// are read in order as several full chunks and a final partial one. If a
+    /// chunk fails, the error names the address and offset it started at.
+    pub async fn read_holding_registers(&mut self, addr: u16, count: u16) -> Result<Vec<u16>> {
+        check_count(count)?;
+        if !self.auto_chunk || usize::from(count) <= MAX_READ_REGISTERS {
+            return self
+                .request(Request::ReadHoldingRegisters(addr, count))
+                .await;
+        }
+        ensure!(
+            usize::from(addr) + usize::from(count) <= usize::from(u16::MAX) + 1,
+            "read of {count} registers at {addr} runs past the end of the address space"
+        );
+
+        let mut data = Vec::with_capacity(usize::from(count));
+        let mut offset = 0;
+        while offset < count {
+            let chunk = (count - offset).min(MAX_READ_REGISTERS as u16);
+            let start = addr + offset;
+            let block = self
+                .request(Request::ReadHoldingRegisters(start, chunk))
+                .await
+                .map_err(|err| {
+                    err.context(format!(
+                        "chunked read failed at address {start} (offset {offset})"
+                    ))
+                })?;
+            data.extend(block);
+            offset += chunk;
+        }
+        Ok(data)
+    }
+
+    /// Like [`read_holding_registers`](Self::read_holding_registers), but
+    /// returns the data as a [`RegisterBlock`] addressed from `addr`.
+    pub async fn read_holding_block(&mut self, addr: u16, count: u16) -> Result<RegisterBlock> {
+        let data = self.read_holding_registers(addr, count).await?;
+        Ok(RegisterBlock::new(addr, data))
+    }
+
+    pub async fn read_input_registers(&mut self, addr: u16, count: u16) -> Result<Vec<u16>> {
+        check_count(count)?;
+        self.request(Request::ReadInputRegisters(addr, count)).await
+    }
+
+    pub async fn read_coils(&mut self, addr: u16, count: u16) -> Result<Vec<u16>> {
+        check_count(count)?;
+        self.request(Request::ReadCoils(addr, count)).await
+    }
+
+    pub async fn read_discrete_inputs(&mut self, addr: u16, count: u16) -> Result<Vec<u16>> {
+        check_count(count)?;
+        self.request(Request::ReadDiscreteInputs(addr, count)).await
+    }
+
+    /// Writes one holding register and checks that the acknowledgement
+    /// echoes the address and value, unless the client was built with
+    /// [`ClientBuilder::no_wait_writes`], failing with a
+    /// [`ModbusException`](crate::ModbusException) if the device refuses.
+    pub async fn write_single_register(&mut self, addr: u16, value: u16) -> Result<()> {
+        let request = Request::WriteSingleRegister(addr, value);
+        if self.no_wait_writes {
+            return self.send_no_wait(request).await;
+        }
+        let echo = self.request(request).await?;
+        self.parsed(check_register_echo(addr, value, &echo))
+    }
+
+    /// Switches one coil on or off and checks that the acknowledgement echoes
+    /// the same address and state, failing with a
+    /// [`ModbusException`](crate::ModbusException) if the device refuses.
+    pub async fn write_single_coil(&mut self, addr: u16, value: bool) -> Result<()> {
+        let request = Request::WriteSingleCoil(addr, value);
+        if self.no_wait_writes {
+            return self.send_no_wait(request).await;
+        }
+        let echo = self.request(request).await?;
+        self.parsed(check_coil_echo(addr, value, &echo))
+    }
+
+    /// Writes `values` to consecutive holding registers starting at `addr`
+    /// and returns how many registers were written. Each acknowledgement has
+    /// to echo its request's start address and register count.
+    ///
+    /// More than [`MAX_WRITE_REGISTERS`] values is an error unless the client
+    /// was built with [`ClientBuilder::split_writes`], in which case the write
+    /// goes out as several requests; a failure part way through leaves the
+    /// earlier requests applied.
+    pub async fn write_multiple_registers(&mut self, addr: u16, values: &[u16]) -> Result<usize> {
+        ensure!(
+            values.len() <= MAX_WRITE_REGISTERS || self.split_writes,
+            "cannot write {} registers in one request, the limit is {MAX_WRITE_REGISTERS}",
+            values.len()
+        );
+        ensure!(
+            usize::from(addr) + values.len() <= usize::from(u16::MAX) + 1,
+            "write of {} registers at {addr} runs past the end of the address space",
+            values.len()
+        );
+
+        let mut written = 0;
+        for chunk in values.chunks(MAX_WRITE_REGISTERS) {
+            let start = addr + written as u16;
+            let request = Request::WriteMultipleRegisters(start, Cow::Owned(chunk.to_vec()));
+            let result = if self.no_wait_writes {
+                self.send_no_wait(request).await
+            } else {
+                match self.request(request).await {
+                    Ok(echo) => self.parsed(check_multiple_echo(start, chunk.len(), &echo)),
+                    Err(err) => Err(err),
+                }
+            };
+            result.map_err(|err| {
+                err.context(format!("write at {start} failed after {written} registers"))
+            })?;
+            written += chunk.len();
+        }
+        Ok(written)
+    }
+
+    /// Reads several holding register blocks in one pipelined batch and
+    /// returns each block tagged with its start address. `ranges` holds
+    /// `(start, count)` pairs. Any failing block fails the whole read; see
+    /// [`read_blocks_partial`](Self::read_blocks_partial) to keep the rest.
+    pub async fn read_blocks(&mut self, ranges: &[(u16, u16)]) -> Result<Vec<(u16, Vec<u16>)>> {
+        self.read_blocks_partial(ranges)
+            .await?
+            .into_iter()
+            .map(|(start, block)| {
+                block
+                    .map(|data| (start, data))
+                    .map_err(|err| err.context(format!("block at {start} failed")))
+            })
+            .collect()
+    }
+
+    /// Like [`read_blocks`](Self::read_blocks), but reports each block's
+    /// result separately, as [`send_batch`](Self::send_batch) does. Only an
+    /// invalid range fails the whole call, before anything is sent.
+    pub async fn read_blocks_partial(
+        &mut self,
+        ranges: &[(u16, u16)],
+    ) -> Result<Vec<(u16, Result<Vec<u16>>)>> {
+        for &(_, count) in ranges {
+            check_count(count)?;
+        }
+        let requests = ranges
+            .iter()
+            .map(|&(start, count)| Request::ReadHoldingRegisters(start, count))
+            .collect();
+        let responses = self.send_batch(requests).await;
+        Ok(ranges
+            .iter()
+            .map(|&(start, _)| start)
+            .zip(responses)
+            .collect())
+    }
+
+    /// Reads the holding registers covered by `map`, starting at `addr`, and
+    /// decodes them into named fields.
+    pub async fn read_holding_register_map(
+        &mut self,
+        addr: u16,
+        map: &RegisterMap,
+    ) -> Result<HashMap<String, Value>> {
+        let count = u16::try_from(map.register_count())
+            .map_err(|_| anyhow!("register map spans too many registers"))?;
+        let data = self.read_holding_registers(addr, count).await?;
+        decode::decode_map(&data, map)
+    }
+
+    /// Reads file records (function code 20), returning the registers of
+    /// each sub-request in order. See [`ModbusException`](crate::ModbusException)
+    /// for devices without support.
+    pub async fn read_file_record(
+        &mut self,
+        sub_requests: &[FileRecordRequest],
+    ) -> Result<Vec<Vec<u16>>> {
+        let request = custom::read_file_record_request(sub_requests)?;
+        let data = self.request(request).await?;
+        self.parsed(custom::parse_read_file_record(&data, sub_requests))
+    }
+
+    /// Reads the device's exception status outputs (function code 7), a
+    /// diagnostic mostly found on serial devices. See
+    /// [`ModbusException`](crate::ModbusException) for devices without
+    /// support.
+    pub async fn read_exception_status(&mut self) -> Result<ExceptionStatus> {
+        let data = self
+            .request(custom::read_exception_status_request())
+            .await?;
+        self.parsed(custom::parse_read_exception_status(&data))
+    }
+
+    /// Reads the FIFO queue (function code 24) whose count register is at
+    /// `pointer`, returning the queued values oldest first. See
+    /// [`ModbusException`](crate::ModbusException) for devices without
+    /// support.
+    pub async fn read_fifo_queue(&mut self, pointer: u16) -> Result<Vec<u16>> {
+        let data = self
+            .request(custom::read_fifo_queue_request(pointer))
+            .await?;
+        self.parsed(custom::parse_read_fifo_queue(&data))
+    }
+
+    /// Reads the device's identification objects (function code 43, MEI
+    /// type 14) up to `category`, following up as long as the device says
+    /// more objects follow. See [`ModbusException`](crate::ModbusException)
+    /// for devices without support.
+    pub async fn read_device_id(
+        &mut self,
+        category: DeviceIdCategory,
+    ) -> Result<DeviceIdentification> {
+        let mut objects = Vec::new();
+        let mut first_object = 0;
+        loop {
+            let data = self
+                .request(custom::read_device_id_request(category, first_object))
+                .await?;
+            let response = self.parsed(custom::parse_read_device_id(&data))?;
+            objects.extend(response.objects);
+            match response.next_object {
+                // Insist on progress, or a confused device keeps us here forever
+                Some(next) if next > first_object => first_object = next,
+                Some(next) => {
+                    bail!("device identification did not advance past object {next:#04x}")
+                }
+                None => break,
+            }
+        }
+        Ok(DeviceIdentification::from_objects(objects))
+    }
+
+    /// Reads any frames the forwarder sent without being asked, waiting up
+    /// to `wait` for each, and returns how many registers each carried.
+    /// A well-behaved forwarder sends none, so anything found points at a
+    /// bug on its side that would otherwise misalign later responses.
+    pub async fn drain_unexpected(&mut self, wait: Duration) -> Result<Vec<usize>> {
+        let mut sizes = Vec::new();
+        if !self.connected {
+            return Ok(sizes);
+        }
+        while let Ok(frame) = tokio::time::timeout(wait, self.reader.next()).await {
+            match frame {
+                Some(data) => sizes.push(data?.len()),
+                // Closed; whatever the forwarder sent has been read
+                None => break,
+            }
+        }
+        if !sizes.is_empty() {
+            self.span
+                .in_scope(|| warn!(frames = sizes.len(), ?sizes, "Unexpected frames"));
+        }
+        Ok(sizes)
+    }
+
+    /// Tells the forwarder we are done and closes the session. A connection
+    /// that was already torn down has nobody left to tell.
+    pub async fn disconnect(mut self) -> Result<()> {
+        if !self.connected {
+            return Ok(());
+        }
+        let result = self.writer.send(Request::Disconnect).await;
+        self.tear_down().await;
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::pin::Pin;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::{Arc, Mutex};
+    use std::task::{Context, Poll};
+
+    use bytes::BytesMut;
+    use tokio::io::{AsyncWrite, AsyncWriteExt, DuplexStream};
+    use tokio_util::codec::Encoder;
+
+    use super::*;
+    use crate::error::{FrameTooLarge, ModbusException};
+    use crate::mock;
+    use crate::outcome::Status;
+
+    // Answers holding register reads with each register's own address
+    fn addresses(request: Request<'static>) -> Option<Vec<u16>> {
+        match request {
+            Request::ReadHoldingRegisters(addr, count) => {
+                Some((0..count).map(|i| addr.wrapping_add(i)).collect())
+            }
+            _ => None,
+        }
+    }
+
+    #[tokio::test]
+    async fn request_is_flushed_before_waiting() {
+        // The mock only answers what reaches it, so a request left in the
+        // write buffer would wait forever
+        let mut client = ModbusForwarderClient::builder("mock").build(mock::spawn(addresses));
+        let read =
+            tokio::time::timeout(Duration::from_secs(5), client.read_holding_registers(10, 2));
+        assert_eq!(
+            read.await.expect("request never flushed").unwrap(),
+            [10, 11]
+        );
+    }
+
+    #[tokio::test]
+    async fn write_at_the_limit_is_one_request() {
+        let writes = Arc::new(Mutex::new(Vec::new()));
+        let recorded = writes.clone();
+        let mut client = ModbusForwarderClient::builder("mock").build(mock::spawn(
+            move |request| match request {
+                Request::WriteMultipleRegisters(addr, values) => {
+                    recorded.lock().unwrap().push(values.len());
+                    Some(vec![addr, values.len() as u16])
+                }
+                _ => None,
+            },
+        ));
+        let values = [7; MAX_WRITE_REGISTERS];
+        assert_eq!(
+            client.write_multiple_registers(0, &values).await.unwrap(),
+            MAX_WRITE_REGISTERS
+        );
+        assert_eq!(*writes.lock().unwrap(), [MAX_WRITE_REGISTERS]);
+    }
+
+    #[tokio::test]
+    async fn write_over_the_limit_is_refused_unsplit() {
+        // Anything reaching this mock would close the connection
+        let mut client =
+            ModbusForwarderClient::builder("mock").build(mock::scripted(Vec::<Vec<u16>>::new()));
+        let values = [7; MAX_WRITE_REGISTERS + 1];
+        let err = client
+            .write_multiple_registers(0, &values)
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("the limit is 123"), "{err:#}");
+        assert!(client.connected);
+    }
+
+    #[tokio::test]
+    async fn write_over_the_limit_is_split_when_allowed() {
+        let writes = Arc::new(Mutex::new(Vec::new()));
+        let recorded = writes.clone();
+        let mut client = ModbusForwarderClient::builder("mock")
+            .split_writes(true)
+            .build(mock::spawn(move |request| match request {
+                Request::WriteMultipleRegisters(addr, values) => {
+                    recorded.lock().unwrap().push(values.len());
+                    Some(vec![addr, values.len() as u16])
+                }
+                _ => None,
+            }));
+        let values = [7; MAX_WRITE_REGISTERS + 1];
+        assert_eq!(
+            client.write_multiple_registers(0, &values).await.unwrap(),
+            MAX_WRITE_REGISTERS + 1
+        );
+        assert_eq!(*writes.lock().unwrap(), [MAX_WRITE_REGISTERS, 1]);
+    }
+
+    #[tokio::test]
+    async fn multiple_register_write_exception_is_returned() {
+        let mut client =
+            ModbusForwarderClient::builder("mock").build(mock::scripted([vec![0x90, 0x02]]));
+        let err = client
+            .write_multiple_registers(20, &[1, 2, 3])
+            .await
+            .unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<ModbusException>(),
+            Some(&ModbusException {
+                function: 0x10,
+                code: 0x02,
+            })
+        );
+        assert!(
+            err.to_string().contains("failed after 0 registers"),
+            "{err:#}"
+        );
+    }
+
+    #[tokio::test]
+    async fn multiple_register_write_checks_the_echo() {
+        // The first chunk is acknowledged in full, the second one short
+        let mut client = ModbusForwarderClient::builder("mock")
+            .split_writes(true)
+            .build(mock::spawn(|request| match request {
+                Request::WriteMultipleRegisters(addr, values) if addr == 0 => {
+                    Some(vec![addr, values.len() as u16])
+                }
+                Request::WriteMultipleRegisters(addr, values) => {
+                    Some(vec![addr, values.len() as u16 - 1])
+                }
+                _ => None,
+            }));
+        let values = [7; MAX_WRITE_REGISTERS + 2];
+        let err = client
+            .write_multiple_registers(0, &values)
+            .await
+            .unwrap_err();
+        assert!(err.downcast_ref::<ModbusException>().is_none(), "{err:#}");
+        let message = format!("{err:#}");
+        assert!(message.contains("failed after 123 registers"), "{message}");
+        assert!(message.contains("acknowledged as [123, 1]"), "{message}");
+    }
+
+    #[test]
+    fn zero_count_is_refused() {
+        assert!(check_count(0).is_err());
+        assert!(check_count(1).is_ok());
+    }
+
+    #[tokio::test]
+    async fn zero_count_read_is_never_sent() {
+        // Anything reaching this mock would close the connection
+        let mut client =
+            ModbusForwarderClient::builder("mock").build(mock::scripted(Vec::<Vec<u16>>::new()));
+        for err in [
+            client.read_holding_registers(0, 0).await.unwrap_err(),
+            client.read_input_registers(0, 0).await.unwrap_err(),
+            client.read_coils(0, 0).await.unwrap_err(),
+            client.read_discrete_inputs(0, 0).await.unwrap_err(),
+        ] {
+            assert!(
+                err.to_string().contains("count must be at least 1"),
+                "{err:#}"
+            );
+        }
+        assert!(client.connected);
+    }
+
+    thread_local! {
+        // What `Redialable::connect` hands out next, newest last
+        static REDIALS: std::cell::RefCell<Vec<tokio::io::DuplexStream>> =
+            const { std::cell::RefCell::new(Vec::new()) };
+    }
+
+    // A mock transport that can "reconnect" to whichever mock forwarder
+    // the test queued up. Tests run on their own thread, so the queue is
+    // per test.
+    struct Redialable(tokio::io::DuplexStream);
+
+    impl Transport for Redialable {
+        type Reader = tokio::io::ReadHalf<tokio::io::DuplexStream>;
+        type Writer = tokio::io::WriteHalf<tokio::io::DuplexStream>;
+
+        fn into_split(self) -> (Self::Reader, Self::Writer) {
+            tokio::io::split(self.0)
+        }
+
+        fn connect(_addr: &str) -> impl std::future::Future<Output = io::Result<Self>> + Send {
+            let next = REDIALS.with(|redials| redials.borrow_mut().pop());
+            std::future::ready(
+                next.map(Redialable).ok_or_else(|| {
+                    io::Error::new(io::ErrorKind::ConnectionRefused, "no mock queued")
+                }),
+            )
+        }
+    }
+
+    #[tokio::test]
+    async fn dropped_connection_is_torn_down() {
+        let mut client = ModbusForwarderClient::builder("mock").build(mock::scripted([vec![1]]));
+        assert_eq!(client.request(Request::ReadCoils(0, 1)).await.unwrap(), [1]);
+
+        let err = client.request(Request::ReadCoils(0, 1)).await.unwrap_err();
+        assert!(err.downcast_ref::<ConnectionClosed>().is_some(), "{err:#}");
+        assert!(!client.connected);
+        assert!(client.read_buffer().is_empty());
+
+        let err = client.request(Request::ReadCoils(0, 1)).await.unwrap_err();
+        assert!(err.downcast_ref::<NotConnected>().is_some(), "{err:#}");
+    }
+
+    #[tokio::test]
+    async fn reconnect_resumes_on_a_new_connection() {
+        let mut client = ModbusForwarderClient::builder("mock")
+            .reconnect_policy(ReconnectPolicy::Once)
+            .reconnect_backoff(Duration::from_millis(1), Duration::from_millis(1))
+            .build(Redialable(mock::scripted([vec![1]])));
+        assert_eq!(client.request(Request::ReadCoils(0, 1)).await.unwrap(), [1]);
+        assert!(client.request(Request::ReadCoils(0, 1)).await.is_err());
+
+        REDIALS.with(|redials| redials.borrow_mut().push(mock::scripted([vec![2]])));
+        client.reconnect().await.unwrap();
+        assert!(client.connected);
+        assert_eq!(client.request(Request::ReadCoils(0, 1)).await.unwrap(), [2]);
+    }
+
+    #[tokio::test]
+    async fn reconnect_gives_up_per_policy() {
+        let mut client = ModbusForwarderClient::builder("mock")
+            .reconnect_policy(ReconnectPolicy::UpTo(2))
+            .reconnect_backoff(Duration::from_millis(1), Duration::from_millis(1))
+            .build(Redialable(mock::scripted(Vec::<Vec<u16>>::new())));
+        let err = client.reconnect().await.unwrap_err();
+        assert!(err.to_string().contains("after 2 attempts"), "{err:#}");
+        assert!(!client.connected);
+    }
+
+    #[tokio::test]
+    async fn batch_results_line_up_with_requests() {
+        let mut client = ModbusForwarderClient::builder("mock").build(mock::spawn(addresses));
+        let results = client
+            .send_batch(vec![
+                Request::ReadHoldingRegisters(0, 1),
+                Request::ReadHoldingRegisters(20, 2),
+                Request::ReadHoldingRegisters(5, 3),
+            ])
+            .await;
+        let results: Vec<Vec<u16>> = results.into_iter().map(Result::unwrap).collect();
+        assert_eq!(results, [vec![0], vec![20, 21], vec![5, 6, 7]]);
+    }
+
+    #[tokio::test]
+    async fn batch_cut_short_fails_the_rest() {
+        let mut client = ModbusForwarderClient::builder("mock").build(mock::scripted([vec![1]]));
+        let mut results = client
+            .send_batch(vec![Request::ReadCoils(0, 1); 3])
+            .await
+            .into_iter();
+        assert_eq!(results.next().unwrap().unwrap(), [1]);
+        let err = results.next().unwrap().unwrap_err();
+        assert!(err.downcast_ref::<ConnectionClosed>().is_some(), "{err:#}");
+        let err = results.next().unwrap().unwrap_err();
+        assert!(err.downcast_ref::<NotConnected>().is_some(), "{err:#}");
+        assert!(!client.connected);
+    }
+
+    // Accepts and discards writes until `fail` is set, then fails them
+    struct SwitchableWriter {
+        fail: Arc<AtomicBool>,
+    }
+
+    impl AsyncWrite for SwitchableWriter {
+        fn poll_write(
+            self: Pin<&mut Self>,
+            _cx: &mut Context<'_>,
+            buf: &[u8],
+        ) -> Poll<io::Result<usize>> {
+            Poll::Ready(if self.fail.load(Ordering::Relaxed) {
+                Err(io::Error::new(
+                    io::ErrorKind::BrokenPipe,
+                    "injected write failure",
+                ))
+            } else {
+                Ok(buf.len())
+            })
+        }
+
+        fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    // Reads come from a duplex stream the test writes to directly
+    struct FailingWrites {
+        reader: DuplexStream,
+        fail: Arc<AtomicBool>,
+    }
+
+    impl Transport for FailingWrites {
+        type Reader = DuplexStream;
+        type Writer = SwitchableWriter;
+
+        fn into_split(self) -> (Self::Reader, Self::Writer) {
+            (self.reader, SwitchableWriter { fail: self.fail })
+        }
+
+        fn connect(_addr: &str) -> impl std::future::Future<Output = io::Result<Self>> + Send {
+            std::future::ready(Err(io::Error::from(io::ErrorKind::Unsupported)))
+        }
+    }
+
+    #[tokio::test]
+    async fn undecodable_response_ends_the_batch() {
+        let (reader, mut forwarder) = tokio::io::duplex(1024);
+        // A good response, one whose payload is too short to hold a
+        // length, then one that will never be read
+        let mut bytes = BytesMut::new();
+        ModbusDataCodec::default()
+            .encode(vec![1], &mut bytes)
+            .unwrap();
+        bytes.extend_from_slice(&3u64.to_be_bytes());
+        bytes.extend_from_slice(&[0xFF; 3]);
+        ModbusDataCodec::default()
+            .encode(vec![3], &mut bytes)
+            .unwrap();
+        forwarder.write_all(&bytes).await.unwrap();
+
+        let mut client = ModbusForwarderClient::builder("mock").build(FailingWrites {
+            reader,
+            fail: Arc::new(AtomicBool::new(false)),
+        });
+        let mut results = client
+            .send_batch(vec![Request::ReadCoils(0, 1); 3])
+            .await
+            .into_iter();
+        assert_eq!(results.next().unwrap().unwrap(), [1]);
+        let err = results.next().unwrap().unwrap_err();
+        assert!(err.downcast_ref::<UndecodableFrame>().is_some(), "{err:#}");
+        let err = results.next().unwrap().unwrap_err();
+        assert!(err.downcast_ref::<NotConnected>().is_some(), "{err:#}");
+        assert!(!client.connected);
+    }
+
+    #[tokio::test]
+    async fn write_failure_clears_the_read_side() {
+        let (reader, mut forwarder) = tokio::io::duplex(1024);
+        // One whole response, then the start of another
+        let mut bytes = BytesMut::new();
+        ModbusDataCodec::default()
+            .encode(vec![1, 2], &mut bytes)
+            .unwrap();
+        bytes.extend_from_slice(&[0, 0, 0]);
+        forwarder.write_all(&bytes).await.unwrap();
+
+        let fail = Arc::new(AtomicBool::new(false));
+        let mut client = ModbusForwarderClient::builder("mock").build(FailingWrites {
+            reader,
+            fail: fail.clone(),
+        });
+        assert_eq!(
+            client.request(Request::ReadCoils(0, 2)).await.unwrap(),
+            [1, 2]
+        );
+        assert_eq!(client.read_buffer().len(), 3);
+
+        fail.store(true, Ordering::Relaxed);
+        let err = client.request(Request::ReadCoils(0, 2)).await.unwrap_err();
+        assert!(is_connection_error(&err), "{err:#}");
+        assert!(!client.connected);
+        assert!(client.read_buffer().is_empty());
+        assert!(client.writer.write_buffer().is_empty());
+    }
+
+    #[tokio::test]
+    async fn register_write_checks_the_echo() {
+        let mut client =
+            ModbusForwarderClient::builder("mock").build(mock::scripted([vec![7, 42]]));
+        client.write_single_register(7, 42).await.unwrap();
+
+        let mut client =
+            ModbusForwarderClient::builder("mock").build(mock::scripted([vec![7, 43]]));
+        let err = client.write_single_register(7, 42).await.unwrap_err();
+        assert!(err.downcast_ref::<ModbusException>().is_none(), "{err:#}");
+        assert!(
+            err.to_string().contains("acknowledged as [7, 43]"),
+            "{err:#}"
+        );
+    }
+
+    #[tokio::test]
+    async fn register_write_exception_is_returned() {
+        let mut client =
+            ModbusForwarderClient::builder("mock").build(mock::scripted([vec![0x86, 0x02]]));
+        let err = client.write_single_register(7, 42).await.unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<ModbusException>(),
+            Some(&ModbusException {
+                function: 0x06,
+                code: 0x02,
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn coil_write_checks_the_echo() {
+        // A spec echo, and one passing the state on as 1
+        for on in [0xFF00, 1] {
+            let mut client = ModbusForwarderClient::builder("mock")
+                .build(mock::scripted([vec![4, on], vec![4, 0]]));
+            client.write_single_coil(4, true).await.unwrap();
+            client.write_single_coil(4, false).await.unwrap();
+        }
+
+        let mut client = ModbusForwarderClient::builder("mock").build(mock::scripted([vec![4, 0]]));
+        let err = client.write_single_coil(4, true).await.unwrap_err();
+        assert!(err.downcast_ref::<ModbusException>().is_none(), "{err:#}");
+        assert!(err.to_string().contains("as false to 4"), "{err:#}");
+
+        let mut client =
+            ModbusForwarderClient::builder("mock").build(mock::scripted([vec![5, 0xFF00]]));
+        let err = client.write_single_coil(4, true).await.unwrap_err();
+        assert!(err.to_string().contains("as true to 5"), "{err:#}");
+    }
+
+    #[tokio::test]
+    async fn coil_write_exception_is_returned() {
+        let mut client =
+            ModbusForwarderClient::builder("mock").build(mock::scripted([vec![0x85, 0x04]]));
+        let err = client.write_single_coil(4, true).await.unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<ModbusException>(),
+            Some(&ModbusException {
+                function: 0x05,
+                code: 0x04,
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn oversized_response_tears_down() {
+        // A full-size read is 8 + 250 bytes of payload
+        let mut client = ModbusForwarderClient::builder("mock")
+            .max_frame_len(64)
+            .build(mock::spawn(addresses));
+        let err = client
+            .read_holding_registers(0, MAX_READ_REGISTERS as u16)
+            .await
+            .unwrap_err();
+        assert!(err.downcast_ref::<FrameTooLarge>().is_some(), "{err:#}");
+        assert!(err.downcast_ref::<UndecodableFrame>().is_some(), "{err:#}");
+        assert!(!is_connection_error(&err), "{err:#}");
+        assert!(!client.connected);
+        assert!(client.read_buffer().is_empty());
+
+        let err = client.read_holding_registers(0, 1).await.unwrap_err();
+        assert!(err.downcast_ref::<NotConnected>().is_some(), "{err:#}");
+    }
+
+    #[tokio::test]
+    async fn parsed_exception_is_recorded_as_one() {
+        let mut client = ModbusForwarderClient::builder("mock")
+            .record_outcomes(true)
+            .build(mock::scripted([vec![0x87, 0x01], vec![0x86, 0x02]]));
+        assert!(client.read_exception_status().await.is_err());
+        assert!(client.write_single_register(1, 2).await.is_err());
+
+        let outcomes = client.take_outcomes();
+        assert_eq!(outcomes.len(), 2);
+        for outcome in outcomes {
+            assert_eq!(outcome.status, Status::Exception, "{outcome:?}");
+            assert_eq!(outcome.values, None);
+            assert!(outcome.error.unwrap().contains("modbus exception"));
+        }
+    }
+
+    #[tokio::test]
+    async fn parsed_response_is_recorded_as_is() {
+        let mut client = ModbusForwarderClient::builder("mock")
+            .record_outcomes(true)
+            .build(mock::scripted([vec![0x07, 0b11]]));
+        assert_eq!(client.read_exception_status().await.unwrap().raw, 0b11);
+
+        let outcomes = client.take_outcomes();
+        assert_eq!(outcomes[0].status, Status::Ok);
+        assert_eq!(outcomes[0].values.as_deref(), Some(&[0x07, 0b11][..]));
+        assert_eq!(outcomes[0].error, None);
+    }
+}
@@ -0,0 +1,530 @@
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, ensure, Result};
+use bincode::Options;
+use bytes::BytesMut;
+use serde::{Deserialize, Serialize};
+use tokio_modbus::Request;
+use tokio_util::codec::{Decoder, Encoder};
+
+use crate::error::FrameTooLarge;
+
+/// How bincode encodes integers inside a frame's payload. Both ends of a
+/// connection have to use the same one; the forwarder's default build uses
+/// fixint, as `bincode::serialize` does.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum IntEncoding {
+    #[default]
+    Fixint,
+    Varint,
+}
+
+impl IntEncoding {
+    // Decoding fails if the payload doesn't use up the whole frame. A length
+    // prefix that overstates the payload is then caught here instead of
+    // silently succeeding.
+    fn serialize<T: Serialize + ?Sized>(self, value: &T) -> bincode::Result<Vec<u8>> {
+        let options = bincode::DefaultOptions::new().reject_trailing_bytes();
+        match self {
+            IntEncoding::Fixint => options.with_fixint_encoding().serialize(value),
+            IntEncoding::Varint => options.with_varint_encoding().serialize(value),
+        }
+    }
+
+    fn deserialize<'a, T: Deserialize<'a>>(self, bytes: &'a [u8]) -> bincode::Result<T> {
+        let options = bincode::DefaultOptions::new().reject_trailing_bytes();
+        match self {
+            IntEncoding::Fixint => options.with_fixint_encoding().deserialize(bytes),
+            IntEncoding::Varint => options.with_varint_encoding().deserialize(bytes),
+        }
+    }
+}
+
+/// Byte order of the u64 length prefix in front of every frame. Big-endian
+/// unless the forwarder was built otherwise.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Endian {
+    #[default]
+    Big,
+    Little,
+}
+
+impl Endian {
+    fn read_u64(self, bytes: [u8; 8]) -> u64 {
+        match self {
+            Endian::Big => u64::from_be_bytes(bytes),
+            Endian::Little => u64::from_le_bytes(bytes),
+        }
+    }
+
+    fn u64_bytes(self, value: u64) -> [u8; 8] {
+        match self {
+            Endian::Big => value.to_be_bytes(),
+            Endian::Little => value.to_le_bytes(),
+        }
+    }
+}
+
+impl FromStr for Endian {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "big" => Ok(Endian::Big),
+            "little" => Ok(Endian::Little),
+            other => Err(anyhow!("expected big or little, got {other:?}")),
+        }
+    }
+}
+
+/// How response payloads are encoded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ResponseEncoding {
+    /// A bincode `Vec<u16>`, like every other payload.
+    #[default]
+    Bincode,
+    /// Two big-endian bytes per register and nothing else. The frame length
+    /// already gives the register count, so this skips bincode's own length
+    /// and is the cheapest form to decode.
+    Raw,
+}
+
+/// Everything about the framing that both ends have to agree on. Nothing
+/// on the wire says which format a frame uses, so a mismatch shows up as
+/// garbled frames.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct WireFormat {
+    pub int_encoding: IntEncoding,
+    pub prefix: Endian,
+    pub responses: ResponseEncoding,
+}
+
+impl WireFormat {
+    /// The payload length a buffer's frame prefix declares, if the whole
+    /// prefix has arrived.
+    pub fn declared_length(&self, buffer: &[u8]) -> Option<u64> {
+        let prefix = buffer.get(..8)?.try_into().ok()?;
+        Some(self.prefix.read_u64(prefix))
+    }
+}
+
+fn decode_raw_registers(bytes: &[u8]) -> Result<Vec<u16>> {
+    ensure!(
+        bytes.len() % 2 == 0,
+        "raw response of {} bytes is not a whole number of registers",
+        bytes.len()
+    );
+    Ok(bytes
+        .chunks_exact(2)
+        .map(|word| u16::from_be_bytes([word[0], word[1]]))
+        .collect())
+}
+
+fn encode_raw_registers(registers: &[u16]) -> Vec<u8> {
+    registers
+        .iter()
+        .flat_map(|word| word.to_be_bytes())
+        .collect()
+}
+
+impl FromStr for IntEncoding {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "fixint" => Ok(IntEncoding::Fixint),
+            "varint" => Ok(IntEncoding::Varint),
+            other => Err(anyhow!("expected fixint or varint, got {other:?}")),
+        }
+    }
+}
+
+// Fails if a frame's payload is over the limit, whichever way it is going.
+fn check_frame_len(length: usize, max_frame_len: Option<usize>) -> Result<(), FrameTooLarge> {
+    match max_frame_len {
+        Some(max) if length > max => Err(FrameTooLarge { length, max }),
+        _ => Ok(()),
+    }
+}
+
+// Appends a frame around the payload `serialize` produces. Nothing is
+// written to dst until serializing worked, so a failure leaves no partial
+// frame behind and the stream stays usable.
+fn put_frame(
+    prefix: Endian,
+    max_frame_len: Option<usize>,
+    serialize: impl FnOnce() -> Result<Vec<u8>>,
+    dst: &mut BytesMut,
+) -> Result<()> {
+    let serialized = serialize()?;
+    // Nothing has been written, so unlike an oversized frame coming the other
+    // way this leaves the connection usable and isn't a `FrameTooLarge`
+    check_frame_len(serialized.len(), max_frame_len)
+        .map_err(|err| anyhow!("{err}; nothing was written"))?;
+    let length = serialized.len() as u64;
+
+    // Serialize the length and append it to dst
+    dst.extend_from_slice(&prefix.u64_bytes(length));
+    dst.extend_from_slice(&serialized); // Append the serialized payload
+
+    Ok(())
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ModbusDataCodec {
+    format: WireFormat,
+    max_frame_len: Option<usize>,
+    last_decode: Duration,
+    bytes_decoded: u64,
+}
+
+/// Frames `Request`s.
+///
+/// The variants that carry data (`WriteMultipleCoils`,
+/// `WriteMultipleRegisters`, `ReadWriteMultipleRegisters` and `Custom`)
+/// hold it in a `Cow<[T]>`. Serde always deserializes a `Cow` into its
+/// owned form, since bincode can only lend out byte and string slices, so
+/// every decoded request is a genuine `Request<'static>` that owns its data
+/// and does not borrow the read buffer. Encoding only reads the data and
+/// treats borrowed and owned payloads alike.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ModbusRequestCodec {
+    format: WireFormat,
+    max_frame_len: Option<usize>,
+}
+
+impl ModbusDataCodec {
+    pub fn new(format: WireFormat) -> Self {
+        Self {
+            format,
+            max_frame_len: None,
+            last_decode: Duration::ZERO,
+            bytes_decoded: 0,
+        }
+    }
+
+    /// Refuses frames whose payload is longer than `max` bytes, both when
+    /// encoding and as soon as a decoded frame's prefix declares one, before
+    /// any of its payload is buffered.
+    pub fn with_max_frame_len(mut self, max: usize) -> Self {
+        self.max_frame_len = Some(max);
+        self
+    }
+
+    /// Time spent deserializing the most recently decoded frame's payload,
+    /// not counting the wait for its bytes.
+    pub fn last_decode_time(&self) -> Duration {
+        self.last_decode
+    }
+
+    /// Wire bytes of every frame decoded so far, length prefixes included.
+    pub fn bytes_decoded(&self) -> u64 {
+        self.bytes_decoded
+    }
+}
+
+impl ModbusRequestCodec {
+    pub fn new(format: WireFormat) -> Self {
+        Self {
+            format,
+            max_frame_len: None,
+        }
+    }
+
+    /// As for [`ModbusDataCodec::with_max_frame_len`].
+    pub fn with_max_frame_len(mut self, max: usize) -> Self {
+        self.max_frame_len = Some(max);
+        self
+    }
+}
+
+impl Decoder for ModbusRequestCodec {
+    type Item = Request<'static>;
+    type Error = anyhow::Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        // Check if src has enough data to read the length (u64 in this case)
+        if src.len() < 8 {
+            // Wait for more bytes
+            return Ok(None);
+        }
+
+        // Read the length
+        let mut length_bytes = [0u8; 8];
+        length_bytes.copy_from_slice(&src[..8]);
+        let length = usize::try_from(self.format.prefix.read_u64(length_bytes))?;
+        check_frame_len(length, self.max_frame_len)?;
+        // Check if src has enough data for a complete Request
+        if src.len() - 8 < length {
+            // Not enough data, wait for more
+            return Ok(None);
+        }
+
+        // Split the buffer at the end of the complete Request
+        let _ = src.split_to(8); // Remove length bytes
+        let request_bytes = src.split_to(length);
+
+        let request = self.format.int_encoding.deserialize(&request_bytes)?;
+        Ok(Some(request))
+    }
+}
+
+impl Encoder<Request<'static>> for ModbusRequestCodec {
+    type Error = anyhow::Error;
+
+    fn encode(&mut self, item: Request, dst: &mut BytesMut) -> Result<(), Self::Error> {
+        let int_encoding = self.format.int_encoding;
+        put_frame(
+            self.format.prefix,
+            self.max_frame_len,
+            || Ok(int_encoding.serialize(&item)?),
+            dst,
+        )
+    }
+}
+
+impl Decoder for ModbusDataCodec {
+    type Item = Vec<u16>;
+    type Error = anyhow::Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        // Check if src has enough data to read the length (u64 in this case)
+        if src.len() < 8 {
+            // Wait for more bytes
+            return Ok(None);
+        }
+
+        // Read the length
+        let mut length_bytes = [0u8; 8];
+        length_bytes.copy_from_slice(&src[..8]);
+        let length = usize::try_from(self.format.prefix.read_u64(length_bytes))?;
+        check_frame_len(length, self.max_frame_len)?;
+        // Check if src has enough data for a complete Request
+        if src.len() - 8 < length {
+            // Not enough data, wait for more
+            return Ok(None);
+        }
+
+        // Split the buffer at the end of the complete Request
+        let _ = src.split_to(8); // Remove length bytes
+        let request_bytes = src.split_to(length);
+
+        let started = Instant::now();
+        let request = match self.format.responses {
+            ResponseEncoding::Bincode => self.format.int_encoding.deserialize(&request_bytes)?,
+            ResponseEncoding::Raw => decode_raw_registers(&request_bytes)?,
+        };
+        self.last_decode = started.elapsed();
+        self.bytes_decoded += 8 + length as u64;
+        Ok(Some(request))
+    }
+}
+
+impl Encoder<Vec<u16>> for ModbusDataCodec {
+    type Error = anyhow::Error;
+
+    fn encode(&mut self, item: Vec<u16>, dst: &mut BytesMut) -> Result<(), Self::Error> {
+        let format = self.format;
+        put_frame(
+            format.prefix,
+            self.max_frame_len,
+            || match format.responses {
+                ResponseEncoding::Bincode => Ok(format.int_encoding.serialize(&item)?),
+                ResponseEncoding::Raw => Ok(encode_raw_registers(&item)),
+            },
+            dst,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::borrow::Cow;
+
+    use serde::ser::SerializeSeq;
+
+    use super::*;
+
+    // A frame of `payload` whose prefix declares `declared` bytes
+    fn frame(declared: u64, payload: &[u8]) -> BytesMut {
+        let mut buffer = BytesMut::new();
+        buffer.extend_from_slice(&declared.to_be_bytes());
+        buffer.extend_from_slice(payload);
+        buffer
+    }
+
+    #[test]
+    fn overstated_length_is_an_error() {
+        let mut payload = IntEncoding::Fixint.serialize(&vec![1u16, 2]).unwrap();
+        let actual = payload.len() as u64;
+        // Two bytes that belong to no field, but are inside the frame
+        payload.extend_from_slice(&[0, 0]);
+        let mut buffer = frame(actual + 2, &payload);
+        assert!(ModbusDataCodec::default().decode(&mut buffer).is_err());
+    }
+
+    // Borrowed data has to outlive the encoder's `'static` item
+    static VALUES: [u16; 4] = [1, 0x1234, 0xFFFF, 0];
+
+    fn round_trip(request: Request<'static>) -> Request<'static> {
+        let mut codec = ModbusRequestCodec::default();
+        let mut buffer = BytesMut::new();
+        codec.encode(request, &mut buffer).unwrap();
+        let decoded = codec.decode(&mut buffer).unwrap().unwrap();
+        assert!(buffer.is_empty());
+        decoded
+    }
+
+    #[test]
+    fn multiple_register_write_round_trips_owned() {
+        let decoded = round_trip(Request::WriteMultipleRegisters(
+            40,
+            Cow::Borrowed(&VALUES[..]),
+        ));
+        assert_eq!(
+            decoded,
+            Request::WriteMultipleRegisters(40, Cow::Owned(VALUES.to_vec()))
+        );
+        assert!(matches!(
+            decoded,
+            Request::WriteMultipleRegisters(_, Cow::Owned(_))
+        ));
+    }
+
+    #[test]
+    fn other_data_requests_round_trip_owned() {
+        let decoded = round_trip(Request::ReadWriteMultipleRegisters(
+            1,
+            2,
+            3,
+            Cow::Borrowed(&VALUES[..3]),
+        ));
+        assert!(matches!(
+            &decoded,
+            Request::ReadWriteMultipleRegisters(1, 2, 3, Cow::Owned(values)) if values[..] == VALUES[..3]
+        ));
+        let decoded = round_trip(Request::Custom(0x07, Cow::Owned(vec![0xAB])));
+        assert!(matches!(
+            &decoded,
+            Request::Custom(0x07, Cow::Owned(data)) if data[..] == [0xAB]
+        ));
+    }
+
+    #[test]
+    fn prefix_round_trips_in_either_order() {
+        for (prefix, length) in [
+            (Endian::Big, [0, 0, 0, 0, 0, 0, 0, 14]),
+            (Endian::Little, [14, 0, 0, 0, 0, 0, 0, 0]),
+        ] {
+            let format = WireFormat {
+                prefix,
+                ..Default::default()
+            };
+            // A fixint `Vec<u16>` of three: an 8-byte length, then 6 bytes
+            let mut codec = ModbusDataCodec::new(format);
+            let mut buffer = BytesMut::new();
+            codec.encode(vec![1, 2, 3], &mut buffer).unwrap();
+            assert_eq!(buffer[..8], length, "{prefix:?}");
+            assert_eq!(format.declared_length(&buffer), Some(14));
+            assert_eq!(codec.decode(&mut buffer).unwrap().unwrap(), [1, 2, 3]);
+
+            let mut codec = ModbusRequestCodec::new(format);
+            codec
+                .encode(Request::ReadHoldingRegisters(7, 2), &mut buffer)
+                .unwrap();
+            assert_eq!(
+                codec.decode(&mut buffer).unwrap().unwrap(),
+                Request::ReadHoldingRegisters(7, 2)
+            );
+            assert!(buffer.is_empty());
+        }
+    }
+
+    #[test]
+    fn mismatched_prefix_order_is_not_a_frame() {
+        let mut buffer = BytesMut::new();
+        ModbusDataCodec::default()
+            .encode(vec![1, 2, 3], &mut buffer)
+            .unwrap();
+        let little = WireFormat {
+            prefix: Endian::Little,
+            ..Default::default()
+        };
+        // Read the other way round, 14 declares more than 2^59 bytes, which
+        // only a frame length limit can tell from a frame still arriving
+        assert!(ModbusDataCodec::new(little)
+            .decode(&mut buffer)
+            .unwrap()
+            .is_none());
+        assert!(ModbusDataCodec::new(little)
+            .with_max_frame_len(1024)
+            .decode(&mut buffer)
+            .is_err());
+    }
+
+    // Bincode can't write a sequence that doesn't know its length up front
+    struct UnknownLength;
+
+    impl Serialize for UnknownLength {
+        fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            serializer.serialize_seq(None)?.end()
+        }
+    }
+
+    #[test]
+    fn failed_serialize_leaves_dst_alone() {
+        let mut dst = BytesMut::new();
+        ModbusDataCodec::default()
+            .encode(vec![1, 2], &mut dst)
+            .unwrap();
+        let before = dst.clone();
+
+        let err = put_frame(
+            Endian::Big,
+            None,
+            || Ok(IntEncoding::Fixint.serialize(&UnknownLength)?),
+            &mut dst,
+        )
+        .unwrap_err();
+        assert!(err.downcast_ref::<bincode::Error>().is_some(), "{err:#}");
+        assert_eq!(dst, before);
+
+        // The frame already there still decodes
+        assert_eq!(
+            ModbusDataCodec::default()
+                .decode(&mut dst)
+                .unwrap()
+                .unwrap(),
+            [1, 2]
+        );
+    }
+
+    #[test]
+    fn oversized_frame_leaves_dst_alone() {
+        let mut dst = BytesMut::new();
+        let err = ModbusDataCodec::default()
+            .with_max_frame_len(8)
+            .encode(vec![1, 2, 3], &mut dst)
+            .unwrap_err();
+        assert!(err.to_string().contains("over the 8-byte limit"), "{err:#}");
+        assert!(dst.is_empty());
+    }
+
+    #[test]
+    fn oversized_prefix_fails_before_the_payload() {
+        // Only the prefix has arrived
+        let mut buffer = frame(1 << 20, &[]);
+        let err = ModbusDataCodec::default()
+            .with_max_frame_len(1024)
+            .decode(&mut buffer)
+            .unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<FrameTooLarge>(),
+            Some(&FrameTooLarge {
+                length: 1 << 20,
+                max: 1024,
+            })
+        );
+    }
+}
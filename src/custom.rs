@@ -0,0 +1,378 @@
+// Requests for function codes that `tokio_modbus::Request` has no variant
+// for. These go out as `Request::Custom` and the forwarder relays the raw
+// response PDU back, one byte per element, function code first.
+
+use std::borrow::Cow;
+
+use anyhow::{anyhow, ensure, Result};
+use tokio_modbus::Request;
+
+use crate::error::ModbusException;
+
+pub const READ_EXCEPTION_STATUS: u8 = 0x07;
+pub const READ_FILE_RECORD: u8 = 0x14;
+pub const READ_FIFO_QUEUE: u8 = 0x18;
+pub const ENCAPSULATED_INTERFACE: u8 = 0x2B;
+
+/// Most values the spec allows in a FIFO queue.
+pub const MAX_FIFO_COUNT: usize = 31;
+
+// MEI type for Read Device Identification under function code 43
+const MEI_READ_DEVICE_ID: u8 = 0x0E;
+// MEI type, read code, conformity level, more follows, next object, count
+const DEVICE_ID_HEADER_LEN: usize = 6;
+
+const FILE_RECORD_REFERENCE_TYPE: u8 = 0x06;
+// Each sub-request is reference type + file number + record number + length
+const FILE_RECORD_SUB_REQUEST_LEN: usize = 7;
+// Largest byte count the spec allows in a read file record request
+const FILE_RECORD_MAX_BYTE_COUNT: usize = 0xF5;
+
+/// One sub-request of a read file record (function code 20) request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FileRecordRequest {
+    pub file_number: u16,
+    pub record_number: u16,
+    pub record_length: u16,
+}
+
+pub fn read_file_record_request(sub_requests: &[FileRecordRequest]) -> Result<Request<'static>> {
+    let byte_count = sub_requests.len() * FILE_RECORD_SUB_REQUEST_LEN;
+    ensure!(
+        !sub_requests.is_empty(),
+        "read file record needs at least one sub-request"
+    );
+    ensure!(
+        byte_count <= FILE_RECORD_MAX_BYTE_COUNT,
+        "too many file record sub-requests ({})",
+        sub_requests.len()
+    );
+
+    let mut pdu = Vec::with_capacity(1 + byte_count);
+    pdu.push(byte_count as u8);
+    for sub in sub_requests {
+        pdu.push(FILE_RECORD_REFERENCE_TYPE);
+        pdu.extend_from_slice(&sub.file_number.to_be_bytes());
+        pdu.extend_from_slice(&sub.record_number.to_be_bytes());
+        pdu.extend_from_slice(&sub.record_length.to_be_bytes());
+    }
+
+    Ok(Request::Custom(READ_FILE_RECORD, Cow::Owned(pdu)))
+}
+
+/// Splits a read file record response into the registers of each sub-request.
+pub fn parse_read_file_record(
+    data: &[u16],
+    sub_requests: &[FileRecordRequest],
+) -> Result<Vec<Vec<u16>>> {
+    let pdu = response_pdu(READ_FILE_RECORD, data)?;
+    let (&length, mut rest) = pdu
+        .split_first()
+        .ok_or_else(|| anyhow!("empty read file record response"))?;
+    ensure!(
+        rest.len() == usize::from(length),
+        "read file record response declares {length} bytes but carries {}",
+        rest.len()
+    );
+
+    let mut records = Vec::with_capacity(sub_requests.len());
+    while !rest.is_empty() {
+        // The sub-response length covers the reference type and the data
+        let sub_length = usize::from(rest[0]);
+        ensure!(
+            sub_length >= 1 && rest.len() > sub_length,
+            "truncated file record sub-response"
+        );
+        ensure!(
+            rest[1] == FILE_RECORD_REFERENCE_TYPE,
+            "unexpected file record reference type {:#04x}",
+            rest[1]
+        );
+        let record = &rest[2..=sub_length];
+        ensure!(record.len() % 2 == 0, "odd file record data length");
+        records.push(
+            record
+                .chunks_exact(2)
+                .map(|word| u16::from_be_bytes([word[0], word[1]]))
+                .collect(),
+        );
+        rest = &rest[sub_length + 1..];
+    }
+
+    ensure!(
+        records.len() == sub_requests.len(),
+        "expected {} file record sub-responses, got {}",
+        sub_requests.len(),
+        records.len()
+    );
+    Ok(records)
+}
+
+/// The eight exception status outputs of a read exception status
+/// (function code 7) response. What each one means is up to the device.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ExceptionStatus {
+    pub raw: u8,
+}
+
+impl ExceptionStatus {
+    /// Whether output `index` (0 to 7, lowest bit first) is set.
+    pub fn bit(self, index: u8) -> bool {
+        index < 8 && self.raw & (1 << index) != 0
+    }
+
+    /// Every output, lowest bit first.
+    pub fn bits(self) -> [bool; 8] {
+        std::array::from_fn(|index| self.bit(index as u8))
+    }
+
+    /// Output 0, the lowest bit.
+    pub fn output0(self) -> bool {
+        self.bit(0)
+    }
+
+    /// Output 1.
+    pub fn output1(self) -> bool {
+        self.bit(1)
+    }
+
+    /// Output 2.
+    pub fn output2(self) -> bool {
+        self.bit(2)
+    }
+
+    /// Output 3.
+    pub fn output3(self) -> bool {
+        self.bit(3)
+    }
+
+    /// Output 4.
+    pub fn output4(self) -> bool {
+        self.bit(4)
+    }
+
+    /// Output 5.
+    pub fn output5(self) -> bool {
+        self.bit(5)
+    }
+
+    /// Output 6.
+    pub fn output6(self) -> bool {
+        self.bit(6)
+    }
+
+    /// Output 7, the highest bit.
+    pub fn output7(self) -> bool {
+        self.bit(7)
+    }
+}
+
+pub fn read_exception_status_request() -> Request<'static> {
+    Request::Custom(READ_EXCEPTION_STATUS, Cow::Owned(Vec::new()))
+}
+
+pub fn parse_read_exception_status(data: &[u16]) -> Result<ExceptionStatus> {
+    let pdu = response_pdu(READ_EXCEPTION_STATUS, data)?;
+    match pdu[..] {
+        [raw] => Ok(ExceptionStatus { raw }),
+        _ => Err(anyhow!(
+            "exception status response of {} bytes, expected 1",
+            pdu.len()
+        )),
+    }
+}
+
+pub fn read_fifo_queue_request(pointer: u16) -> Request<'static> {
+    Request::Custom(READ_FIFO_QUEUE, Cow::Owned(pointer.to_be_bytes().to_vec()))
+}
+
+/// Extracts the queued values from a read FIFO queue response.
+pub fn parse_read_fifo_queue(data: &[u16]) -> Result<Vec<u16>> {
+    let pdu = response_pdu(READ_FIFO_QUEUE, data)?;
+    ensure!(pdu.len() >= 4, "truncated FIFO queue response");
+    let byte_count = usize::from(u16::from_be_bytes([pdu[0], pdu[1]]));
+    let count = usize::from(u16::from_be_bytes([pdu[2], pdu[3]]));
+    ensure!(
+        count <= MAX_FIFO_COUNT,
+        "FIFO queue reports {count} values, more than the {MAX_FIFO_COUNT} allowed"
+    );
+    // The byte count covers the FIFO count and the values
+    ensure!(
+        byte_count == 2 + 2 * count && pdu.len() == 2 + byte_count,
+        "FIFO queue response of {} bytes doesn't match its {count} values",
+        pdu.len()
+    );
+    Ok(pdu[4..]
+        .chunks_exact(2)
+        .map(|word| u16::from_be_bytes([word[0], word[1]]))
+        .collect())
+}
+
+/// Which group of device identification objects to read.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeviceIdCategory {
+    /// Vendor name, product code and revision, which every device has.
+    Basic = 0x01,
+    /// The basic objects plus vendor URL, product and model name and user
+    /// application name, where the device has them.
+    Regular = 0x02,
+    /// Regular plus the device's private objects.
+    Extended = 0x03,
+}
+
+/// One response to a read device identification request. Devices that
+/// can't fit every object in one response set `next_object`, and the read
+/// continues from there.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeviceIdResponse {
+    pub conformity_level: u8,
+    pub next_object: Option<u8>,
+    pub objects: Vec<(u8, Vec<u8>)>,
+}
+
+/// Device identification objects, decoded as text.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DeviceIdentification {
+    pub vendor_name: String,
+    pub product_code: String,
+    pub revision: String,
+    pub vendor_url: Option<String>,
+    pub product_name: Option<String>,
+    pub model_name: Option<String>,
+    pub user_application_name: Option<String>,
+    /// Objects beyond the standard ones, by object ID, left undecoded.
+    pub other: Vec<(u8, Vec<u8>)>,
+}
+
+impl DeviceIdentification {
+    pub fn from_objects(objects: impl IntoIterator<Item = (u8, Vec<u8>)>) -> Self {
+        let mut identification = Self::default();
+        for (id, value) in objects {
+            let text = || String::from_utf8_lossy(&value).into_owned();
+            match id {
+                0x00 => identification.vendor_name = text(),
+                0x01 => identification.product_code = text(),
+                0x02 => identification.revision = text(),
+                0x03 => identification.vendor_url = Some(text()),
+                0x04 => identification.product_name = Some(text()),
+                0x05 => identification.model_name = Some(text()),
+                0x06 => identification.user_application_name = Some(text()),
+                _ => identification.other.push((id, value)),
+            }
+        }
+        identification
+    }
+}
+
+pub fn read_device_id_request(category: DeviceIdCategory, first_object: u8) -> Request<'static> {
+    let pdu = vec![MEI_READ_DEVICE_ID, category as u8, first_object];
+    Request::Custom(ENCAPSULATED_INTERFACE, Cow::Owned(pdu))
+}
+
+pub fn parse_read_device_id(data: &[u16]) -> Result<DeviceIdResponse> {
+    let pdu = response_pdu(ENCAPSULATED_INTERFACE, data)?;
+    ensure!(
+        pdu.len() >= DEVICE_ID_HEADER_LEN,
+        "truncated device identification response"
+    );
+    ensure!(
+        pdu[0] == MEI_READ_DEVICE_ID,
+        "unexpected MEI type {:#04x} in device identification response",
+        pdu[0]
+    );
+    let conformity_level = pdu[2];
+    let next_object = (pdu[3] == 0xFF).then_some(pdu[4]);
+    let count = usize::from(pdu[5]);
+
+    let mut rest = &pdu[DEVICE_ID_HEADER_LEN..];
+    let mut objects = Vec::with_capacity(count);
+    for _ in 0..count {
+        ensure!(rest.len() >= 2, "truncated device identification object");
+        let (id, length) = (rest[0], usize::from(rest[1]));
+        ensure!(
+            rest.len() >= 2 + length,
+            "device identification object {id:#04x} declares {length} bytes but carries {}",
+            rest.len() - 2
+        );
+        objects.push((id, rest[2..2 + length].to_vec()));
+        rest = &rest[2 + length..];
+    }
+    ensure!(
+        rest.is_empty(),
+        "{} trailing bytes after device identification objects",
+        rest.len()
+    );
+
+    Ok(DeviceIdResponse {
+        conformity_level,
+        next_object,
+        objects,
+    })
+}
+
+// Unpacks a relayed response into the PDU data following the function code,
+// turning an exception response into a `ModbusException` error.
+fn response_pdu(function: u8, data: &[u16]) -> Result<Vec<u8>> {
+    let bytes = data
+        .iter()
+        .map(|&element| {
+            u8::try_from(element)
+                .map_err(|_| anyhow!("custom response element {element:#06x} is not a byte"))
+        })
+        .collect::<Result<Vec<u8>>>()?;
+    let (&code, rest) = bytes
+        .split_first()
+        .ok_or_else(|| anyhow!("empty response to function {function:#04x}"))?;
+
+    if code == function | 0x80 {
+        let exception = rest
+            .first()
+            .copied()
+            .ok_or_else(|| anyhow!("exception response without an exception code"))?;
+        return Err(ModbusException {
+            function,
+            code: exception,
+        }
+        .into());
+    }
+    ensure!(
+        code == function,
+        "response function code {code:#04x} does not match request {function:#04x}"
+    );
+
+    Ok(rest.to_vec())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exception_status_outputs_are_named() {
+        let status = parse_read_exception_status(&[0x07, 0b1010_0101]).unwrap();
+        assert_eq!(status.raw, 0b1010_0101);
+        assert_eq!(
+            [
+                status.output0(),
+                status.output1(),
+                status.output2(),
+                status.output3(),
+                status.output4(),
+                status.output5(),
+                status.output6(),
+                status.output7(),
+            ],
+            status.bits()
+        );
+        assert_eq!(
+            status.bits(),
+            [true, false, true, false, false, true, false, true]
+        );
+    }
+
+    #[test]
+    fn exception_status_exception_is_returned() {
+        let err = parse_read_exception_status(&[0x87, 0x01]).unwrap_err();
+        assert!(err.downcast_ref::<ModbusException>().is_some(), "{err:#}");
+    }
+}
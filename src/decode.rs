@@ -0,0 +1,283 @@
+// Typed decoding of register data. Multi-register values are assembled from
+// bytes according to an `Order`, then read as big-endian.
+
+use std::collections::HashMap;
+
+use anyhow::{ensure, Result};
+
+/// Which register carries the most significant word of a multi-register value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum WordOrder {
+    #[default]
+    Big,
+    Little,
+}
+
+/// Byte order within each register.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ByteOrder {
+    #[default]
+    Big,
+    Little,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Order {
+    pub word: WordOrder,
+    pub byte: ByteOrder,
+}
+
+// Assembles one value spanning `registers` into an integer.
+fn assemble(registers: &[u16], order: Order) -> u64 {
+    let word_at = |i: usize| match order.word {
+        WordOrder::Big => registers[i],
+        WordOrder::Little => registers[registers.len() - 1 - i],
+    };
+    (0..registers.len()).fold(0, |value, i| {
+        let word = match order.byte {
+            ByteOrder::Big => word_at(i),
+            ByteOrder::Little => word_at(i).swap_bytes(),
+        };
+        (value << 16) | u64::from(word)
+    })
+}
+
+fn decode_values<T>(
+    registers: &[u16],
+    width: usize,
+    order: Order,
+    convert: impl Fn(u64) -> T,
+) -> Result<Vec<T>> {
+    ensure!(
+        registers.len() % width == 0,
+        "{} registers is not a multiple of {width}",
+        registers.len()
+    );
+    Ok(registers
+        .chunks_exact(width)
+        .map(|chunk| convert(assemble(chunk, order)))
+        .collect())
+}
+
+pub fn decode_u32(registers: &[u16], order: Order) -> Result<Vec<u32>> {
+    decode_values(registers, 2, order, |value| value as u32)
+}
+
+pub fn decode_i32(registers: &[u16], order: Order) -> Result<Vec<i32>> {
+    decode_values(registers, 2, order, |value| value as u32 as i32)
+}
+
+pub fn decode_f32(registers: &[u16], order: Order) -> Result<Vec<f32>> {
+    decode_values(registers, 2, order, |value| f32::from_bits(value as u32))
+}
+
+pub fn decode_u64(registers: &[u16], order: Order) -> Result<Vec<u64>> {
+    decode_values(registers, 4, order, |value| value)
+}
+
+pub fn decode_i64(registers: &[u16], order: Order) -> Result<Vec<i64>> {
+    decode_values(registers, 4, order, |value| value as i64)
+}
+
+pub fn decode_f64(registers: &[u16], order: Order) -> Result<Vec<f64>> {
+    decode_values(registers, 4, order, f64::from_bits)
+}
+
+/// Packs coil or discrete input states, one per element and non-zero for
+/// on, into bytes the way Modbus does: the first bit is the least
+/// significant bit of the first byte. Bits past the end of `bits` in the
+/// last byte are zero.
+pub fn pack_bits(bits: &[u16]) -> Vec<u8> {
+    bits.chunks(8)
+        .map(|byte| {
+            byte.iter()
+                .enumerate()
+                .filter(|&(_, &bit)| bit != 0)
+                .fold(0, |packed, (i, _)| packed | 1 << i)
+        })
+        .collect()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FieldType {
+    U16,
+    I16,
+    U32,
+    I32,
+    F32,
+    U64,
+    I64,
+    F64,
+}
+
+impl FieldType {
+    /// Number of registers a field of this type spans.
+    pub fn width(self) -> usize {
+        match self {
+            FieldType::U16 | FieldType::I16 => 1,
+            FieldType::U32 | FieldType::I32 | FieldType::F32 => 2,
+            FieldType::U64 | FieldType::I64 | FieldType::F64 => 4,
+        }
+    }
+
+    fn value(self, raw: u64) -> Value {
+        match self {
+            FieldType::U16 => Value::U16(raw as u16),
+            FieldType::I16 => Value::I16(raw as u16 as i16),
+            FieldType::U32 => Value::U32(raw as u32),
+            FieldType::I32 => Value::I32(raw as u32 as i32),
+            FieldType::F32 => Value::F32(f32::from_bits(raw as u32)),
+            FieldType::U64 => Value::U64(raw),
+            FieldType::I64 => Value::I64(raw as i64),
+            FieldType::F64 => Value::F64(f64::from_bits(raw)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Value {
+    U16(u16),
+    I16(i16),
+    U32(u32),
+    I32(i32),
+    F32(f32),
+    U64(u64),
+    I64(i64),
+    F64(f64),
+}
+
+/// A named field at a register offset within a block.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Field {
+    pub name: String,
+    pub offset: usize,
+    pub field_type: FieldType,
+}
+
+/// Describes where typed fields live within one contiguous register block.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RegisterMap {
+    pub fields: Vec<Field>,
+    pub order: Order,
+}
+
+impl RegisterMap {
+    pub fn new(order: Order) -> Self {
+        Self {
+            fields: Vec::new(),
+            order,
+        }
+    }
+
+    pub fn field(mut self, name: impl Into<String>, offset: usize, field_type: FieldType) -> Self {
+        self.fields.push(Field {
+            name: name.into(),
+            offset,
+            field_type,
+        });
+        self
+    }
+
+    /// Number of registers a block must hold to cover every field.
+    pub fn register_count(&self) -> usize {
+        self.fields
+            .iter()
+            .map(|field| field.offset + field.field_type.width())
+            .max()
+            .unwrap_or(0)
+    }
+}
+
+// Decodes one field from exactly the registers it spans.
+pub(crate) fn decode_field(registers: &[u16], field_type: FieldType, order: Order) -> Value {
+    debug_assert_eq!(registers.len(), field_type.width());
+    field_type.value(assemble(registers, order))
+}
+
+/// Decodes every field of `map` out of a block read starting at offset 0.
+pub fn decode_map(registers: &[u16], map: &RegisterMap) -> Result<HashMap<String, Value>> {
+    map.fields
+        .iter()
+        .map(|field| {
+            let end = field.offset + field.field_type.width();
+            ensure!(
+                end <= registers.len(),
+                "field {} at offset {} needs {end} registers, block has {}",
+                field.name,
+                field.offset,
+                registers.len()
+            );
+            let value = decode_field(&registers[field.offset..end], field.field_type, map.order);
+            Ok((field.name.clone(), value))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BIG: Order = Order {
+        word: WordOrder::Big,
+        byte: ByteOrder::Big,
+    };
+    // Word-swapped, as many devices lay out 64-bit values
+    const LITTLE_WORDS: Order = Order {
+        word: WordOrder::Little,
+        byte: ByteOrder::Big,
+    };
+
+    #[test]
+    fn u64_in_both_orders() {
+        let cases = [
+            (BIG, [0x0102, 0x0304, 0x0506, 0x0708]),
+            (LITTLE_WORDS, [0x0708, 0x0506, 0x0304, 0x0102]),
+        ];
+        for (order, registers) in cases {
+            assert_eq!(
+                decode_u64(&registers, order).unwrap(),
+                [0x0102_0304_0506_0708],
+                "{order:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn i64_in_both_orders() {
+        let cases = [
+            (BIG, [0xFFFF, 0xFFFF, 0xFFFF, 0xFFFE], -2),
+            (LITTLE_WORDS, [0xFFFE, 0xFFFF, 0xFFFF, 0xFFFF], -2),
+            (BIG, [0x7FFF, 0xFFFF, 0xFFFF, 0xFFFF], i64::MAX),
+            (LITTLE_WORDS, [0x0000, 0x0000, 0x0000, 0x8000], i64::MIN),
+        ];
+        for (order, registers, expected) in cases {
+            assert_eq!(
+                decode_i64(&registers, order).unwrap(),
+                [expected],
+                "{order:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn f64_in_both_orders() {
+        let cases = [
+            (BIG, [0x3FF8, 0, 0, 0], 1.5),
+            (LITTLE_WORDS, [0, 0, 0, 0x3FF8], 1.5),
+            (BIG, [0xC000, 0, 0, 0], -2.0),
+            (LITTLE_WORDS, [0, 0, 0, 0xC000], -2.0),
+        ];
+        for (order, registers, expected) in cases {
+            assert_eq!(
+                decode_f64(&registers, order).unwrap(),
+                [expected],
+                "{order:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn partial_64_bit_value_is_an_error() {
+        assert!(decode_u64(&[1, 2, 3, 4, 5], BIG).is_err());
+    }
+}
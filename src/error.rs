@@ -0,0 +1,160 @@
+use std::fmt;
+
+/// An exception response relayed by the forwarder from the device.
+///
+/// This is how a device turns down any request: a register or coil it won't
+/// write, or a function code it doesn't support at all, as many devices
+/// don't support file records, FIFO queues, exception status or device
+/// identification. The client's methods return it for those instead of a
+/// response.
+///
+/// Returned inside an `anyhow::Error`; callers that care can
+/// `downcast_ref::<ModbusException>()` to tell it apart from transport errors.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ModbusException {
+    pub function: u8,
+    pub code: u8,
+}
+
+impl ModbusException {
+    pub fn description(&self) -> &'static str {
+        match self.code {
+            0x01 => "illegal function",
+            0x02 => "illegal data address",
+            0x03 => "illegal data value",
+            0x04 => "server device failure",
+            0x05 => "acknowledge",
+            0x06 => "server device busy",
+            0x08 => "memory parity error",
+            0x0A => "gateway path unavailable",
+            0x0B => "gateway target device failed to respond",
+            _ => "unknown exception",
+        }
+    }
+}
+
+impl fmt::Display for ModbusException {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "modbus exception {:#04x} ({}) for function {:#04x}",
+            self.code,
+            self.description(),
+            self.function
+        )
+    }
+}
+
+impl std::error::Error for ModbusException {}
+
+// An exception relayed in answer to a standard request: the function code
+// with its high bit set, then the exception code, as for custom requests.
+pub(crate) fn relayed_exception(function: u8, data: &[u16]) -> Option<ModbusException> {
+    match *data {
+        [code, exception] if code == u16::from(function | 0x80) => Some(ModbusException {
+            function,
+            code: u8::try_from(exception).ok()?,
+        }),
+        _ => None,
+    }
+}
+
+/// The forwarder closed the connection before answering.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ConnectionClosed;
+
+impl fmt::Display for ConnectionClosed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("connection closed by forwarder")
+    }
+}
+
+impl std::error::Error for ConnectionClosed {}
+
+/// A request was made after the connection was torn down and before a
+/// successful reconnect.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NotConnected;
+
+impl fmt::Display for NotConnected {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("not connected to the forwarder")
+    }
+}
+
+impl std::error::Error for NotConnected {}
+
+/// The session has read as many bytes as
+/// [`ClientBuilder::max_bytes_read`](crate::ClientBuilder::max_bytes_read)
+/// allows, and the client disconnected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ByteCapReached {
+    pub cap: u64,
+    pub read: u64,
+}
+
+impl fmt::Display for ByteCapReached {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "read {} bytes, reaching the cap of {}",
+            self.read, self.cap
+        )
+    }
+}
+
+impl std::error::Error for ByteCapReached {}
+
+/// A response frame's length prefix declared a payload over
+/// [`ClientBuilder::max_frame_len`](crate::ClientBuilder::max_frame_len).
+/// The payload is never read, so nothing after it can be framed either. It
+/// comes wrapped in an [`UndecodableFrame`] and is handled the same way: the
+/// client drops the connection, but it isn't a
+/// [connection error](is_connection_error), since the forwarder would most
+/// likely send the same frame again.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FrameTooLarge {
+    pub length: usize,
+    pub max: usize,
+}
+
+impl fmt::Display for FrameTooLarge {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "frame payload of {} bytes is over the {}-byte limit",
+            self.length, self.max
+        )
+    }
+}
+
+impl std::error::Error for FrameTooLarge {}
+
+/// A response frame arrived but couldn't be decoded. The reader stops at
+/// the first such frame, so the client drops the connection, but the error
+/// isn't a [connection error](is_connection_error): a retry would most
+/// likely meet the same frame again.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UndecodableFrame;
+
+impl fmt::Display for UndecodableFrame {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("undecodable response frame")
+    }
+}
+
+impl std::error::Error for UndecodableFrame {}
+
+/// Whether an error means the connection itself is gone, as opposed to a
+/// single request failing on an otherwise healthy connection.
+///
+/// An [`UndecodableFrame`] never is, even when decoding failed on an I/O
+/// error such as a payload ending early.
+pub fn is_connection_error(err: &anyhow::Error) -> bool {
+    if err.downcast_ref::<UndecodableFrame>().is_some() {
+        return false;
+    }
+    err.chain().any(|cause| {
+        cause.is::<std::io::Error>() || cause.is::<ConnectionClosed>() || cause.is::<NotConnected>()
+    })
+}
@@ -0,0 +1,18 @@
+use std::time::SystemTime;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectionState {
+    Connecting,
+    Connected,
+    Disconnected,
+    Reconnecting,
+}
+
+/// A change in the client's connection lifecycle, as seen by subscribers of
+/// [`ModbusForwarderClient::subscribe`](crate::ModbusForwarderClient::subscribe).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConnectionEvent {
+    pub state: ConnectionState,
+    pub peer: String,
+    pub at: SystemTime,
+}
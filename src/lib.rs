@@ -0,0 +1,24 @@
+pub mod backoff;
+pub mod block;
+#[cfg(feature = "blocking")]
+pub mod blocking;
+pub mod client;
+pub mod codec;
+pub mod custom;
+pub mod decode;
+pub mod error;
+pub mod events;
+pub mod mock;
+pub mod outcome;
+pub mod poll;
+pub mod proxy;
+pub mod rate;
+pub mod snapshot;
+pub mod transport;
+
+pub use block::RegisterBlock;
+pub use client::{ClientBuilder, ConnectionInfo, ModbusForwarderClient, RequestTimings};
+pub use error::ModbusException;
+pub use outcome::RequestOutcome;
+pub use poll::StopHandle;
+pub use transport::{ProvidedStream, Transport};
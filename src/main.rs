@@ -1,126 +1,1522 @@
-use anyhow::Result;
-use bytes::BytesMut;
-use futures::{SinkExt, StreamExt};
+use std::fs::{File, OpenOptions};
+use std::future::Future;
+use std::io::Write;
+use std::num::ParseIntError;
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::{anyhow, bail, ensure, Context, Result};
+use clap::{Parser, Subcommand, ValueEnum};
+use modbus_forwarder_client_test::backoff::{
+    jittered, ReconnectPolicy, RetryPolicy, DEFAULT_JITTER_PERCENT,
+};
+use modbus_forwarder_client_test::codec::{Endian, IntEncoding, ResponseEncoding};
+use modbus_forwarder_client_test::custom::{
+    DeviceIdCategory, DeviceIdentification, ExceptionStatus, FileRecordRequest,
+    ENCAPSULATED_INTERFACE, READ_EXCEPTION_STATUS, READ_FIFO_QUEUE, READ_FILE_RECORD,
+};
+use modbus_forwarder_client_test::decode::pack_bits;
+use modbus_forwarder_client_test::error::{is_connection_error, ByteCapReached};
+use modbus_forwarder_client_test::proxy::Socks5Proxy;
+use modbus_forwarder_client_test::{mock, ModbusException, ModbusForwarderClient, Transport};
+use tokio::time::{Instant, MissedTickBehavior};
 use tokio_modbus::Request;
-use tokio_util::codec::{Encoder, Decoder, FramedRead, FramedWrite};
-use tracing::info;
+use tracing::{error, info, warn, Instrument};
+use tracing_subscriber::EnvFilter;
+
+#[derive(Parser)]
+#[command(about = "Test client for the modbus forwarder")]
+struct Cli {
+    /// Forwarder address
+    #[arg(long, default_value = "127.0.0.1:1234")]
+    addr: String,
+
+    /// Random spread applied to poll intervals and reconnect backoff, as a
+    /// percentage of the base delay
+    #[arg(long, default_value_t = DEFAULT_JITTER_PERCENT, value_parser = clap::value_parser!(u8).range(0..=100))]
+    timeout_jitter: u8,
+
+    /// How often to try reconnecting after losing the connection: never,
+    /// once, forever, or a number of attempts
+    #[arg(long, default_value = "never")]
+    reconnect_policy: ReconnectPolicy,
+
+    /// Times to resend a request that failed with a transport error such as
+    /// a timeout or reset; exception responses are never retried
+    #[arg(long, default_value_t = 0)]
+    retries: u32,
+
+    /// Don't wait for write acknowledgements, as for broadcast writes. A
+    /// write sent this way reports success even if the device rejected it
+    #[arg(long)]
+    no_wait: bool,
+
+    /// Only print explicitly requested data and errors. Informational logs
+    /// are cut down to warnings unless RUST_LOG says otherwise
+    #[arg(long, short)]
+    quiet: bool,
+
+    /// Pause between successive requests of the demo sequence or a scan, to
+    /// give fragile devices breathing room. Not counted as part of any
+    /// request
+    #[arg(long, default_value_t = 0)]
+    inter_request_delay_ms: u64,
+
+    /// Split holding register reads of more than 125 registers into several
+    /// requests and join the results
+    #[arg(long)]
+    auto_chunk: bool,
+
+    /// Send at most this many requests per second, to go easy on a shared
+    /// forwarder at the cost of throughput
+    #[arg(long)]
+    max_rps: Option<u32>,
+
+    /// Log the read buffer's contents every time a response has been
+    /// awaited this long, to diagnose hangs
+    #[arg(long)]
+    hang_dump_after_ms: Option<u64>,
+
+    /// Disconnect and stop once this many bytes have been read from the
+    /// forwarder, as a guardrail on metered links
+    #[arg(long)]
+    max_total_bytes: Option<u64>,
+
+    /// Fail the demo sequence if the forwarder sends responses nobody
+    /// asked for, instead of only warning about them
+    #[arg(long)]
+    strict: bool,
 
-struct ModbusDataCodec;
-struct ModbusRequestCodec;
+    /// Fail as soon as a register read by demo, poll or scan holds this
+    /// value, e.g. 0xFFFF for a fault marker. May be repeated
+    #[arg(long, value_parser = parse_u16)]
+    fail_on_value: Vec<u16>,
 
-impl Decoder for ModbusRequestCodec {
-    type Item = Request<'static>;
-    type Error = anyhow::Error;
+    /// Only apply --fail-on-value to registers in this inclusive address
+    /// range, written START-END
+    #[arg(long, value_parser = parse_address_range, requires = "fail_on_value")]
+    fail_on_range: Option<RangeInclusive<u16>>,
+
+    /// Reach the forwarder through a SOCKS5 proxy,
+    /// socks5://[user:password@]host:port. Needs the socks5 feature
+    #[arg(long)]
+    proxy: Option<Socks5Proxy>,
+
+    /// Integer encoding the forwarder was built with: fixint or varint
+    #[arg(long, default_value = "fixint")]
+    int_encoding: IntEncoding,
+
+    /// Byte order of the forwarder's frame length prefixes: big or little
+    #[arg(long, default_value = "big")]
+    prefix_endian: Endian,
+
+    /// Expect responses as raw big-endian registers instead of bincode.
+    /// Only for forwarders built to send them that way
+    #[arg(long)]
+    raw_responses: bool,
+
+    /// How long poll and pattern may take to finish the request in flight
+    /// and disconnect once told to stop, before giving up on both
+    #[arg(long, default_value_t = 5000)]
+    shutdown_grace_ms: u64,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Run the fixed read sequence (the default)
+    Demo,
+    /// Read one block repeatedly, reconnecting per --reconnect-policy if the
+    /// forwarder goes away
+    Poll {
+        #[arg(long, value_enum, default_value_t = ReadKind::Holding)]
+        kind: ReadKind,
+        #[arg(long, default_value_t = 0)]
+        start: u16,
+        #[arg(long, default_value_t = 16, value_parser = clap::value_parser!(u16).range(1..))]
+        count: u16,
+        #[arg(long, default_value_t = 1000)]
+        interval_ms: u64,
+        /// Only print values that changed since the previous poll, as
+        /// `addr: old -> new` lines
+        #[arg(long)]
+        diff: bool,
+        /// What --diff prints for the first poll, which has no baseline
+        #[arg(long, value_enum, default_value_t = FirstPoll::All)]
+        diff_first: FirstPoll,
+        /// Prefix each printed response with the time it arrived
+        #[arg(long, value_enum, default_value_t = TimestampFormat::Off)]
+        timestamp: TimestampFormat,
+        /// Only print the values at these comma-separated offsets into the
+        /// response, as `addr: value` lines
+        #[arg(long, value_delimiter = ',', conflicts_with = "diff")]
+        sample_registers: Vec<usize>,
+        /// How coil and discrete input states are printed
+        #[arg(long, value_enum, default_value_t = BitFormat::List, conflicts_with_all = ["diff", "sample_registers"])]
+        bits: BitFormat,
+        /// Also append each response to this file as a line of JSON with
+        /// its time and the read that produced it
+        #[arg(long)]
+        output: Option<PathBuf>,
+        /// Move the output file to `<file>.1` and start a new one before it
+        /// would grow past this many bytes
+        #[arg(long, requires = "output", value_parser = clap::value_parser!(u64).range(1..))]
+        max_output_bytes: Option<u64>,
+    },
+    /// Write consecutive holding registers
+    Write {
+        #[arg(long)]
+        start: u16,
+        #[arg(required = true)]
+        values: Vec<u16>,
+    },
+    /// Write one holding register, read it back and fail unless the two
+    /// agree
+    VerifyWrite {
+        /// Register to write
+        #[arg(long)]
+        register: u16,
+        #[arg(long)]
+        value: u16,
+    },
+    /// Poll one holding register that should always hold the same value,
+    /// such as a heartbeat, and report every read where it doesn't
+    WatchConstant {
+        /// Register to watch
+        #[arg(long, alias = "addr")]
+        register: u16,
+        /// Value the register should hold
+        #[arg(long, value_parser = parse_u16)]
+        expect: u16,
+        #[arg(long, default_value_t = 1000)]
+        interval_ms: u64,
+        /// Fail on the first deviation instead of counting them until
+        /// stopped
+        #[arg(long)]
+        exit_on_deviation: bool,
+    },
+    /// Write a generated signal to one holding register until Ctrl-C
+    Pattern {
+        /// Register to write
+        #[arg(long)]
+        register: u16,
+        #[arg(long, value_enum, default_value_t = PatternMode::Ramp)]
+        mode: PatternMode,
+        #[arg(long, default_value_t = 500)]
+        interval_ms: u64,
+        /// Lowest value written
+        #[arg(long, default_value_t = 0)]
+        min: u16,
+        /// Highest value written
+        #[arg(long, default_value_t = 100)]
+        max: u16,
+        /// Writes per sine cycle
+        #[arg(long, default_value_t = 20, value_parser = clap::value_parser!(u32).range(1..))]
+        period: u32,
+    },
+    /// Run every request type against an in-memory mock forwarder and
+    /// check the results; no forwarder needed
+    Selftest,
+    /// Read a list of blocks from a file, one `kind,start,count` per line
+    Scan {
+        /// File listing the blocks; `#` starts a comment, and malformed
+        /// lines are reported and skipped
+        #[arg(long)]
+        addresses_from_file: PathBuf,
+    },
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum FirstPoll {
+    /// Print every value as `addr: value`
+    All,
+    /// Print nothing and just record the baseline
+    Skip,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum TimestampFormat {
+    Off,
+    /// RFC 3339 in UTC with millisecond precision
+    Rfc3339,
+    /// Milliseconds since the Unix epoch
+    EpochMs,
+}
 
-    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
-        // Check if src has enough data to read the length (u64 in this case)
-        if src.len() < 8 {
-            // Wait for more bytes
-            return Ok(None);
+impl TimestampFormat {
+    // Prefix for a line of data output produced now, separator included
+    fn prefix(self) -> String {
+        let now = SystemTime::now();
+        match self {
+            TimestampFormat::Off => String::new(),
+            TimestampFormat::Rfc3339 => format!("{} ", humantime::format_rfc3339_millis(now)),
+            TimestampFormat::EpochMs => {
+                let millis = now
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap_or_default()
+                    .as_millis();
+                format!("{millis} ")
+            }
         }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum BitFormat {
+    /// One element per bit, as for registers
+    List,
+    /// Packed bytes in hex, first bit in the low bit of the first byte,
+    /// followed by the bit count
+    Hex,
+    /// One digit per bit in address order, in groups of eight
+    Binary,
+}
 
-        // Read the length
-        let mut length_bytes = [0u8; 8];
-        length_bytes.copy_from_slice(&src[..8]);
-        let length = usize::try_from(u64::from_be_bytes(length_bytes))?;
-        // Check if src has enough data for a complete Request
-        if src.len() - 8 < length {
-            // Not enough data, wait for more
-            return Ok(None);
+impl BitFormat {
+    fn format(self, bits: &[u16]) -> String {
+        match self {
+            BitFormat::List => format!("{bits:?}"),
+            BitFormat::Hex => {
+                let hex: String = pack_bits(bits)
+                    .iter()
+                    .map(|byte| format!("{byte:02x}"))
+                    .collect();
+                format!("{hex} ({} bits)", bits.len())
+            }
+            BitFormat::Binary => bits
+                .chunks(8)
+                .map(|group| {
+                    group
+                        .iter()
+                        .map(|&bit| if bit != 0 { '1' } else { '0' })
+                        .collect::<String>()
+                })
+                .collect::<Vec<_>>()
+                .join(" "),
         }
+    }
+}
 
-        // Split the buffer at the end of the complete Request
-        let _ = src.split_to(8); // Remove length bytes
-        let request_bytes = src.split_to(length);
+#[derive(Clone, Copy, ValueEnum)]
+enum PatternMode {
+    /// Count up from min to max, then start over
+    Ramp,
+    /// Swing from min to max and back once per period, starting at min
+    Sine,
+}
 
-        let request = bincode::deserialize(&request_bytes)?;
-        Ok(Some(request))
+impl PatternMode {
+    fn value(self, tick: u64, min: u16, max: u16, period: u32) -> u16 {
+        let span = max - min;
+        match self {
+            PatternMode::Ramp => min + (tick % (u64::from(span) + 1)) as u16,
+            PatternMode::Sine => {
+                let phase = (tick % u64::from(period)) as f64 / f64::from(period);
+                let level = (1.0 - (phase * std::f64::consts::TAU).cos()) / 2.0;
+                min + (level * f64::from(span)).round() as u16
+            }
+        }
     }
 }
 
-impl Encoder<Request<'static>> for ModbusRequestCodec {
-    type Error = anyhow::Error;
+#[derive(Clone, Copy, ValueEnum)]
+enum ReadKind {
+    Holding,
+    Input,
+    Coils,
+    Discretes,
+}
 
-    fn encode(&mut self, item: Request, dst: &mut BytesMut) -> Result<(), Self::Error> {
-        let serialized = bincode::serialize(&item)?;
-        let length = serialized.len() as u64;
+impl ReadKind {
+    fn name(self) -> &'static str {
+        match self {
+            ReadKind::Holding => "holding",
+            ReadKind::Input => "input",
+            ReadKind::Coils => "coils",
+            ReadKind::Discretes => "discretes",
+        }
+    }
 
-        // Serialize the length and append it to dst
-        dst.extend_from_slice(&length.to_be_bytes()); // Big endian format
-        dst.extend_from_slice(&serialized); // Append the serialized Request
+    fn is_bits(self) -> bool {
+        matches!(self, ReadKind::Coils | ReadKind::Discretes)
+    }
 
+    // Bit reads come back padded to whole bytes. The padding isn't data, so
+    // it's cut off here, before anything compares or prints the states.
+    async fn read<T: Transport>(
+        self,
+        client: &mut ModbusForwarderClient<T>,
+        start: u16,
+        count: u16,
+    ) -> Result<Vec<u16>> {
+        let mut data = match self {
+            ReadKind::Holding => client.read_holding_registers(start, count).await?,
+            ReadKind::Input => client.read_input_registers(start, count).await?,
+            ReadKind::Coils => client.read_coils(start, count).await?,
+            ReadKind::Discretes => client.read_discrete_inputs(start, count).await?,
+        };
+        if self.is_bits() {
+            data.truncate(usize::from(count));
+        }
+        Ok(data)
+    }
+}
+
+// Accepts decimal or 0x-prefixed hex
+fn parse_u16(s: &str) -> Result<u16, ParseIntError> {
+    match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        Some(hex) => u16::from_str_radix(hex, 16),
+        None => s.parse(),
+    }
+}
+
+fn parse_address_range(s: &str) -> Result<RangeInclusive<u16>> {
+    let (start, end) = s
+        .split_once('-')
+        .ok_or_else(|| anyhow!("expected START-END, got {s:?}"))?;
+    let (start, end) = (parse_u16(start.trim())?, parse_u16(end.trim())?);
+    ensure!(start <= end, "range start {start} is above its end {end}");
+    Ok(start..=end)
+}
+
+/// Register values that mean the device is reporting a fault.
+struct Sentinels {
+    values: Vec<u16>,
+    range: Option<RangeInclusive<u16>>,
+}
+
+impl Sentinels {
+    // Fails on the first register of a block read at `start` that holds a
+    // sentinel value
+    fn check(&self, start: u16, data: &[u16]) -> Result<()> {
+        for (i, &value) in data.iter().enumerate() {
+            let address = usize::from(start) + i;
+            let checked = match &self.range {
+                Some(range) => u16::try_from(address).is_ok_and(|a| range.contains(&a)),
+                None => true,
+            };
+            if checked && self.values.contains(&value) {
+                bail!("register {address} holds sentinel value {value:#06x}");
+            }
+        }
         Ok(())
     }
 }
 
-impl Decoder for ModbusDataCodec {
-    type Item = Vec<u16>;
-    type Error = anyhow::Error;
+#[tokio::main(flavor = "current_thread")]
+async fn main() -> Result<()> {
+    let cli = Cli::parse();
+    let default_filter = if cli.quiet { "warn" } else { "info" };
+    tracing_subscriber::fmt()
+        .with_writer(std::io::stderr)
+        .with_env_filter(
+            EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new(default_filter)),
+        )
+        .init();
+
+    let command = cli.command.unwrap_or(Command::Demo);
+    let sentinels = Sentinels {
+        values: cli.fail_on_value,
+        range: cli.fail_on_range,
+    };
+    // The self-test brings its own forwarder
+    if matches!(command, Command::Selftest) {
+        return selftest().await;
+    }
+
+    let mut builder = ModbusForwarderClient::builder(&cli.addr);
+    if let Some(proxy) = cli.proxy {
+        builder = builder.proxy(proxy);
+    }
+    if let Some(limit) = cli.max_rps {
+        builder = builder.max_requests_per_second(limit);
+    }
+    if let Some(bytes) = cli.max_total_bytes {
+        builder = builder.max_bytes_read(bytes);
+    }
+    if let Some(ms) = cli.hang_dump_after_ms {
+        builder = builder.hang_dump_after(Duration::from_millis(ms));
+    }
+    let mut client = builder
+        .timeout_jitter(cli.timeout_jitter)
+        .reconnect_policy(cli.reconnect_policy)
+        .retry_policy(RetryPolicy::new(cli.retries))
+        .auto_chunk(cli.auto_chunk)
+        .no_wait_writes(cli.no_wait)
+        .int_encoding(cli.int_encoding)
+        .prefix_endian(cli.prefix_endian)
+        .response_encoding(if cli.raw_responses {
+            ResponseEncoding::Raw
+        } else {
+            ResponseEncoding::Bincode
+        })
+        .connect()
+        .await?;
+
+    let result = match command {
+        Command::Selftest => unreachable!("the self-test runs without connecting"),
+        Command::Scan {
+            addresses_from_file,
+        } => {
+            let contents = std::fs::read_to_string(&addresses_from_file)
+                .with_context(|| format!("failed to read {}", addresses_from_file.display()))?;
+            let delay = Duration::from_millis(cli.inter_request_delay_ms);
+            scan(client, &contents, &sentinels, delay).await
+        }
+        Command::Demo => {
+            let options = DemoOptions {
+                inter_request_delay: Duration::from_millis(cli.inter_request_delay_ms),
+                quiet: cli.quiet,
+                strict: cli.strict,
+                sentinels,
+            };
+            demo(client, options).await
+        }
+        Command::Poll {
+            kind,
+            start,
+            count,
+            interval_ms,
+            diff,
+            diff_first,
+            timestamp,
+            sample_registers,
+            bits,
+            output,
+            max_output_bytes,
+        } => {
+            if bits != BitFormat::List && !kind.is_bits() {
+                bail!("--bits only applies to coils and discretes");
+            }
+            let output = output
+                .map(|path| NdjsonOutput::open(path, max_output_bytes))
+                .transpose()?;
+            let span = client.span().clone();
+            let options = PollOptions {
+                kind,
+                start,
+                count,
+                interval: Duration::from_millis(interval_ms),
+                jitter_percent: cli.timeout_jitter,
+                grace: Duration::from_millis(cli.shutdown_grace_ms),
+                diff: diff.then_some(diff_first),
+                timestamp,
+                samples: sample_registers,
+                bits,
+                sentinels,
+                output,
+            };
+            poll(client, options).instrument(span).await
+        }
+        Command::Write { start, values } => {
+            let written = client.write_multiple_registers(start, &values).await?;
+            if !cli.quiet {
+                if cli.no_wait {
+                    println!("Sent {written} registers without waiting for acknowledgement");
+                } else {
+                    println!("Wrote {written} registers");
+                }
+            }
+            client.disconnect().await
+        }
+        Command::VerifyWrite { register, value } => {
+            if cli.no_wait {
+                bail!("verify-write needs the write to be acknowledged; drop --no-wait");
+            }
+            verify_write(client, register, value).await
+        }
+        Command::WatchConstant {
+            register,
+            expect,
+            interval_ms,
+            exit_on_deviation,
+        } => {
+            let span = client.span().clone();
+            let options = WatchOptions {
+                register,
+                expect,
+                interval: Duration::from_millis(interval_ms),
+                jitter_percent: cli.timeout_jitter,
+                grace: Duration::from_millis(cli.shutdown_grace_ms),
+                exit_on_deviation,
+            };
+            watch_constant(client, options).instrument(span).await
+        }
+        Command::Pattern {
+            register,
+            mode,
+            interval_ms,
+            min,
+            max,
+            period,
+        } => {
+            if min > max {
+                bail!("--min {min} is above --max {max}");
+            }
+            let options = PatternOptions {
+                register,
+                mode,
+                interval: Duration::from_millis(interval_ms),
+                grace: Duration::from_millis(cli.shutdown_grace_ms),
+                min,
+                max,
+                period,
+            };
+            pattern(client, options, cli.quiet).await
+        }
+    };
+    // Hitting the byte cap is a deliberate stop, not a failure
+    match result {
+        Err(err) if err.downcast_ref::<ByteCapReached>().is_some() => {
+            warn!("Stopped: {err:#}");
+            Ok(())
+        }
+        result => result,
+    }
+}
+
+// How a write followed by a read-back went. A register the device won't
+// take is a different failure from one that accepted the write but reads
+// back something else.
+#[derive(Debug, PartialEq)]
+enum WriteCheck {
+    Matched,
+    Rejected(ModbusException),
+    ReadsBack(u16),
+}
 
-    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
-        // Check if src has enough data to read the length (u64 in this case)
-        if src.len() < 8 {
-            // Wait for more bytes
-            return Ok(None);
+async fn check_write<T: Transport>(
+    client: &mut ModbusForwarderClient<T>,
+    register: u16,
+    value: u16,
+) -> Result<WriteCheck> {
+    if let Err(err) = client.write_single_register(register, value).await {
+        if let Some(&exception) = err.downcast_ref::<ModbusException>() {
+            return Ok(WriteCheck::Rejected(exception));
         }
+        return Err(err.context(format!("write of {value} to {register} failed")));
+    }
+    let read_back = client
+        .read_holding_registers(register, 1)
+        .await
+        .with_context(|| format!("read-back of {register} failed"))?;
+    let actual = *read_back
+        .first()
+        .ok_or_else(|| anyhow!("read-back of {register} returned no registers"))?;
+    Ok(if actual == value {
+        WriteCheck::Matched
+    } else {
+        WriteCheck::ReadsBack(actual)
+    })
+}
 
-        // Read the length
-        let mut length_bytes = [0u8; 8];
-        length_bytes.copy_from_slice(&src[..8]);
-        let length = usize::try_from(u64::from_be_bytes(length_bytes))?;
-        // Check if src has enough data for a complete Request
-        if src.len() - 8 < length {
-            // Not enough data, wait for more
-            return Ok(None);
+async fn verify_write(mut client: ModbusForwarderClient, register: u16, value: u16) -> Result<()> {
+    let check = check_write(&mut client, register, value).await?;
+    client.disconnect().await?;
+    let actual = match check {
+        WriteCheck::Matched => value,
+        WriteCheck::Rejected(exception) => {
+            bail!("register {register} rejected the write of {value}: {exception}")
         }
+        WriteCheck::ReadsBack(actual) => actual,
+    };
+    println!("Wrote {register}: {value}");
+    println!("Read back {register}: {actual}");
+    ensure!(
+        actual == value,
+        "register {register} reads back {actual} after writing {value}"
+    );
+    Ok(())
+}
 
-        // Split the buffer at the end of the complete Request
-        let _ = src.split_to(8); // Remove length bytes
-        let request_bytes = src.split_to(length);
+struct PatternOptions {
+    register: u16,
+    mode: PatternMode,
+    interval: Duration,
+    grace: Duration,
+    min: u16,
+    max: u16,
+    period: u32,
+}
 
-        let request = bincode::deserialize(&request_bytes)?;
-        Ok(Some(request))
+async fn pattern(
+    mut client: ModbusForwarderClient,
+    options: PatternOptions,
+    quiet: bool,
+) -> Result<()> {
+    let mut ticks = tokio::time::interval(options.interval);
+    // A slow acknowledgement pushes the schedule back instead of bunching up
+    // the writes that follow it
+    ticks.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    let stop = stop_requested()?;
+    tokio::pin!(stop);
+    let mut deadline = None;
+
+    for tick in 0u64.. {
+        tokio::select! {
+            _ = &mut stop => break,
+            _ = ticks.tick() => {}
+        }
+        let value = options
+            .mode
+            .value(tick, options.min, options.max, options.period);
+        let written = {
+            let write = client.write_single_register(options.register, value);
+            tokio::pin!(write);
+            tokio::select! {
+                written = &mut write => written,
+                _ = &mut stop => {
+                    let at = Instant::now() + options.grace;
+                    deadline = Some(at);
+                    match tokio::time::timeout_at(at, write).await {
+                        Ok(written) => written,
+                        Err(_) => {
+                            warn!("Write still unacknowledged after {:?}", options.grace);
+                            break;
+                        }
+                    }
+                }
+            }
+        };
+        if let Err(err) = written {
+            let deadline = deadline.unwrap_or_else(|| Instant::now() + options.grace);
+            disconnect_by(client, deadline).await?;
+            return Err(err.context(format!("write of {value} to {} failed", options.register)));
+        }
+        if !quiet {
+            println!("{}: {value}", options.register);
+        }
+        if deadline.is_some() {
+            break;
+        }
     }
+    disconnect_by(
+        client,
+        deadline.unwrap_or_else(|| Instant::now() + options.grace),
+    )
+    .await
 }
 
-impl Encoder<Vec<u16>> for ModbusDataCodec {
-    type Error = anyhow::Error;
+struct PollOptions {
+    kind: ReadKind,
+    start: u16,
+    count: u16,
+    interval: Duration,
+    jitter_percent: u8,
+    grace: Duration,
+    // Print only changes, and what to do with the first poll
+    diff: Option<FirstPoll>,
+    timestamp: TimestampFormat,
+    // Response offsets to print instead of the whole response
+    samples: Vec<usize>,
+    bits: BitFormat,
+    sentinels: Sentinels,
+    output: Option<NdjsonOutput>,
+}
 
-    fn encode(&mut self, item: Vec<u16>, dst: &mut BytesMut) -> Result<(), Self::Error> {
-        let serialized = bincode::serialize(&item)?;
-        let length = serialized.len() as u64;
+// Appends one JSON object per line. Each line goes out in a single
+// unbuffered write, so someone tailing the file sees it straight away.
+struct NdjsonOutput {
+    path: PathBuf,
+    file: File,
+    written: u64,
+    // Rotate before the file would grow past this
+    max_bytes: Option<u64>,
+}
 
-        // Serialize the length and append it to dst
-        dst.extend_from_slice(&length.to_be_bytes()); // Big endian format
-        dst.extend_from_slice(&serialized); // Append the serialized Request
+impl NdjsonOutput {
+    fn open(path: PathBuf, max_bytes: Option<u64>) -> Result<Self> {
+        let file = append_to(&path)?;
+        let written = file.metadata()?.len();
+        Ok(Self {
+            path,
+            file,
+            written,
+            max_bytes,
+        })
+    }
+
+    fn write(&mut self, record: &serde_json::Value) -> Result<()> {
+        let mut line = serde_json::to_vec(record)?;
+        line.push(b'\n');
+        let len = line.len() as u64;
+        // A line longer than the limit still gets a file of its own
+        if self
+            .max_bytes
+            .is_some_and(|max| self.written > 0 && self.written + len > max)
+        {
+            self.rotate()?;
+        }
+        self.file
+            .write_all(&line)
+            .with_context(|| format!("failed to write to {}", self.path.display()))?;
+        self.written += len;
+        Ok(())
+    }
 
+    // Keeps a single previous file, replacing any older one
+    fn rotate(&mut self) -> Result<()> {
+        let mut rotated = self.path.clone().into_os_string();
+        rotated.push(".1");
+        std::fs::rename(&self.path, &rotated)
+            .with_context(|| format!("failed to rotate {}", self.path.display()))?;
+        self.file = append_to(&self.path)?;
+        self.written = 0;
         Ok(())
     }
 }
 
+fn append_to(path: &Path) -> Result<File> {
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .with_context(|| format!("failed to open {}", path.display()))
+}
 
+// Answers the self-test's requests as a well-behaved forwarder would, with
+// data derived from each request so the client's results can be checked.
+fn selftest_device(request: Request<'static>) -> Option<Vec<u16>> {
+    let registers =
+        |addr: u16, count: u16| -> Vec<u16> { (0..count).map(|i| addr.wrapping_add(i)).collect() };
+    let bits = |addr: u16, count: u16| -> Vec<u16> {
+        (0..count).map(|i| addr.wrapping_add(i) % 2).collect()
+    };
+    Some(match request {
+        Request::ReadHoldingRegisters(addr, count) => registers(addr, count),
+        Request::ReadInputRegisters(addr, count) => registers(addr, count)
+            .into_iter()
+            .map(|value| !value)
+            .collect(),
+        Request::ReadCoils(addr, count) | Request::ReadDiscreteInputs(addr, count) => {
+            bits(addr, count)
+        }
+        // Illegal data address
+        Request::WriteSingleRegister(SELFTEST_READ_ONLY, _) => vec![0x86, 0x02],
+        Request::WriteSingleRegister(addr, value) => vec![addr, value],
+        Request::WriteSingleCoil(addr, value) => vec![addr, if value { 0xFF00 } else { 0 }],
+        Request::WriteMultipleRegisters(addr, values) => vec![addr, values.len() as u16],
+        Request::Custom(READ_FILE_RECORD, pdu) => selftest_file_records(&pdu),
+        Request::Custom(READ_FIFO_QUEUE, _) => selftest_fifo_queue(),
+        Request::Custom(READ_EXCEPTION_STATUS, _) => {
+            vec![
+                READ_EXCEPTION_STATUS.into(),
+                SELFTEST_EXCEPTION_STATUS.into(),
+            ]
+        }
+        Request::Custom(ENCAPSULATED_INTERFACE, _) => selftest_device_id(),
+        _ => return None,
+    })
+}
 
-#[tokio::main(flavor = "current_thread")]
-async fn main() -> Result<()> {
-    let socket = tokio::net::TcpStream::connect("127.0.0.1:1234").await?;
-    println!("Connected to server");
-    let (reader, writer) = tokio::io::split(socket);
-    let mut client_reader = FramedRead::new(reader, ModbusDataCodec);
-    let mut client_writer = FramedWrite::new(writer, ModbusRequestCodec);
-
-    client_writer.send(Request::ReadHoldingRegisters(0, 16)).await?;
-    let data = client_reader.next().await.unwrap()?;
-    println!("Holding Register Data is: {data:?}");
-    client_writer.send(Request::ReadCoils(0, 3)).await?;
-    let data = client_reader.next().await.unwrap()?;
-    println!("Coils Data is: {data:?}");
-    client_writer.send(Request::ReadDiscreteInputs(0, 16)).await?;
-    let data = client_reader.next().await.unwrap()?;
-    println!("Discretes Data is: {data:?}");
-    client_writer.send(Request::ReadInputRegisters(0, 16)).await?;
-    let data = client_reader.next().await.unwrap()?;
-    println!("Input Register Data is: {data:?}");
-    client_writer.send(Request::Disconnect).await?;
+// Each record holds consecutive values starting at its record number
+fn selftest_file_records(pdu: &[u8]) -> Vec<u16> {
+    let mut response = vec![READ_FILE_RECORD, 0];
+    for sub in pdu.get(1..).unwrap_or_default().chunks_exact(7) {
+        let record = u16::from_be_bytes([sub[3], sub[4]]);
+        let length = u16::from_be_bytes([sub[5], sub[6]]);
+        response.push((1 + 2 * length) as u8);
+        response.push(0x06);
+        for i in 0..length {
+            response.extend(record.wrapping_add(i).to_be_bytes());
+        }
+    }
+    response[1] = (response.len() - 2) as u8;
+    response.into_iter().map(u16::from).collect()
+}
+
+const SELFTEST_FIFO: [u16; 3] = [0x1111, 0x2222, 0x3333];
+
+fn selftest_fifo_queue() -> Vec<u16> {
+    let count = SELFTEST_FIFO.len() as u16;
+    let mut response = vec![READ_FIFO_QUEUE];
+    response.extend((2 + 2 * count).to_be_bytes());
+    response.extend(count.to_be_bytes());
+    for value in SELFTEST_FIFO {
+        response.extend(value.to_be_bytes());
+    }
+    response.into_iter().map(u16::from).collect()
+}
 
+const SELFTEST_EXCEPTION_STATUS: u8 = 0b1010_0101;
+
+// Refuses writes. Every other register takes them, but reads back its own
+// address whatever was written.
+const SELFTEST_READ_ONLY: u16 = 900;
+
+const SELFTEST_DEVICE_ID: [&str; 3] = ["mock", "SELFTEST", "1.0"];
+
+fn selftest_device_id() -> Vec<u16> {
+    // MEI type, read code, conformity level, no more follows, next object
+    let mut response = vec![ENCAPSULATED_INTERFACE, 0x0E, 0x01, 0x01, 0x00, 0x00];
+    response.push(SELFTEST_DEVICE_ID.len() as u8);
+    for (id, value) in SELFTEST_DEVICE_ID.iter().enumerate() {
+        response.push(id as u8);
+        response.push(value.len() as u8);
+        response.extend(value.bytes());
+    }
+    response.into_iter().map(u16::from).collect()
+}
+
+fn expect<T: PartialEq + std::fmt::Debug>(actual: Result<T>, expected: T) -> Result<()> {
+    let actual = actual?;
+    ensure!(actual == expected, "expected {expected:?}, got {actual:?}");
+    Ok(())
+}
+
+async fn selftest() -> Result<()> {
+    let mut client = ModbusForwarderClient::builder("mock").build(mock::spawn(selftest_device));
+    let file_records = [
+        FileRecordRequest {
+            file_number: 1,
+            record_number: 5,
+            record_length: 3,
+        },
+        FileRecordRequest {
+            file_number: 2,
+            record_number: 100,
+            record_length: 1,
+        },
+    ];
+    let device_id = DeviceIdentification {
+        vendor_name: SELFTEST_DEVICE_ID[0].to_owned(),
+        product_code: SELFTEST_DEVICE_ID[1].to_owned(),
+        revision: SELFTEST_DEVICE_ID[2].to_owned(),
+        ..Default::default()
+    };
+
+    let results = [
+        (
+            "read holding registers",
+            expect(
+                client.read_holding_registers(10, 4).await,
+                vec![10, 11, 12, 13],
+            ),
+        ),
+        (
+            "read input registers",
+            expect(client.read_input_registers(0, 2).await, vec![!0, !1]),
+        ),
+        (
+            "read coils",
+            expect(client.read_coils(3, 3).await, vec![1, 0, 1]),
+        ),
+        (
+            "read discrete inputs",
+            expect(client.read_discrete_inputs(0, 2).await, vec![0, 1]),
+        ),
+        (
+            "write single register",
+            client.write_single_register(7, 42).await,
+        ),
+        ("write single coil", client.write_single_coil(4, true).await),
+        (
+            "verify write",
+            expect(check_write(&mut client, 30, 30).await, WriteCheck::Matched),
+        ),
+        (
+            "verify write to a read-only register",
+            expect(
+                check_write(&mut client, SELFTEST_READ_ONLY, 1).await,
+                WriteCheck::Rejected(ModbusException {
+                    function: 0x06,
+                    code: 0x02,
+                }),
+            ),
+        ),
+        (
+            "verify write that reads back differently",
+            expect(
+                check_write(&mut client, 31, 5).await,
+                WriteCheck::ReadsBack(31),
+            ),
+        ),
+        (
+            "write multiple registers",
+            expect(client.write_multiple_registers(20, &[1, 2, 3]).await, 3),
+        ),
+        (
+            "batch",
+            expect(
+                client
+                    .send_batch(vec![
+                        Request::ReadHoldingRegisters(0, 1),
+                        Request::ReadCoils(0, 2),
+                    ])
+                    .await
+                    .into_iter()
+                    .collect(),
+                vec![vec![0], vec![0, 1]],
+            ),
+        ),
+        (
+            "read file record",
+            expect(
+                client.read_file_record(&file_records).await,
+                vec![vec![5, 6, 7], vec![100]],
+            ),
+        ),
+        (
+            "read FIFO queue",
+            expect(client.read_fifo_queue(0).await, SELFTEST_FIFO.to_vec()),
+        ),
+        (
+            "read exception status",
+            expect(
+                client.read_exception_status().await,
+                ExceptionStatus {
+                    raw: SELFTEST_EXCEPTION_STATUS,
+                },
+            ),
+        ),
+        (
+            "read device identification",
+            expect(
+                client.read_device_id(DeviceIdCategory::Basic).await,
+                device_id,
+            ),
+        ),
+        ("disconnect", client.disconnect().await),
+    ];
+
+    let total = results.len();
+    let mut failed = 0;
+    for (name, result) in results {
+        match result {
+            Ok(()) => println!("PASS {name}"),
+            Err(err) => {
+                println!("FAIL {name}: {err:#}");
+                failed += 1;
+            }
+        }
+    }
+    if failed > 0 {
+        bail!("{failed} of {total} self-test checks failed");
+    }
     Ok(())
-}
\ No newline at end of file
+}
+
+// Resolves once the process is asked to shut down: SIGTERM on Unix,
+// Ctrl-Break on Windows. The handler is installed before this returns, so a
+// signal that arrives before the future is first polled isn't lost.
+#[cfg(unix)]
+fn termination() -> std::io::Result<impl Future<Output = ()>> {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut sigterm = signal(SignalKind::terminate())?;
+    Ok(async move {
+        sigterm.recv().await;
+    })
+}
+
+#[cfg(windows)]
+fn termination() -> std::io::Result<impl Future<Output = ()>> {
+    let mut ctrl_break = tokio::signal::windows::ctrl_break()?;
+    Ok(async move {
+        ctrl_break.recv().await;
+    })
+}
+
+// Resolves on Ctrl-C or a termination request, whichever comes first.
+fn stop_requested() -> std::io::Result<impl Future<Output = ()>> {
+    let terminate = termination()?;
+    Ok(async move {
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => {}
+            _ = terminate => {}
+        }
+    })
+}
+
+// Sends the Disconnect on the way out, unless that would run past the
+// deadline, as it would against a forwarder that stopped reading.
+async fn disconnect_by<T: Transport>(
+    client: ModbusForwarderClient<T>,
+    deadline: Instant,
+) -> Result<()> {
+    tokio::time::timeout_at(deadline, client.disconnect())
+        .await
+        .map_err(|_| anyhow!("disconnect didn't complete within the shutdown grace period"))?
+}
+
+// Waits for `read` even if a stop is requested meanwhile, but then only for
+// the grace period, which also sets the `deadline` for the Disconnect. `None`
+// means the read didn't finish in time.
+async fn read_unless_stopped<R>(
+    read: impl Future<Output = Result<R>>,
+    stop: Pin<&mut impl Future<Output = ()>>,
+    grace: Duration,
+    deadline: &mut Option<Instant>,
+) -> Option<Result<R>> {
+    tokio::pin!(read);
+    tokio::select! {
+        result = &mut read => Some(result),
+        _ = stop => {
+            let at = Instant::now() + grace;
+            *deadline = Some(at);
+            match tokio::time::timeout_at(at, read).await {
+                Ok(result) => Some(result),
+                Err(_) => {
+                    warn!("Read still unanswered after {grace:?}");
+                    None
+                }
+            }
+        }
+    }
+}
+
+// Ctrl-C or a termination request never interrupts a read. One that arrives
+// mid-read gives the read the grace period to finish, then the loop stops;
+// between polls, or while waiting to reconnect, it stops straight away.
+// Either way the Disconnect on the way out has to fit in the same grace
+// period.
+async fn poll(mut client: ModbusForwarderClient, mut options: PollOptions) -> Result<()> {
+    let stop = stop_requested()?;
+    tokio::pin!(stop);
+    let mut previous: Option<Vec<u16>> = None;
+    let mut deadline = None;
+    loop {
+        let read = options.kind.read(&mut client, options.start, options.count);
+        let Some(result) =
+            read_unless_stopped(read, stop.as_mut(), options.grace, &mut deadline).await
+        else {
+            break;
+        };
+        match result {
+            Ok(data) => {
+                if let Err(err) = options.sentinels.check(options.start, &data) {
+                    let deadline = deadline.unwrap_or_else(|| Instant::now() + options.grace);
+                    disconnect_by(client, deadline).await?;
+                    return Err(err);
+                }
+                if let Some(output) = &mut options.output {
+                    output.write(&serde_json::json!({
+                        "time": humantime::format_rfc3339_millis(SystemTime::now()).to_string(),
+                        "kind": options.kind.name(),
+                        "start": options.start,
+                        "count": options.count,
+                        "values": data,
+                    }))?;
+                }
+                let prefix = options.timestamp.prefix();
+                match options.diff {
+                    Some(first) => {
+                        print_diff(&prefix, options.start, previous.as_deref(), &data, first);
+                        previous = Some(data);
+                    }
+                    None if !options.samples.is_empty() => {
+                        print_samples(&prefix, options.start, &data, &options.samples);
+                    }
+                    None if options.kind.is_bits() => {
+                        println!("{prefix}{}", options.bits.format(&data));
+                    }
+                    None => println!("{prefix}{data:?}"),
+                }
+            }
+            Err(err) if err.downcast_ref::<ByteCapReached>().is_some() => return Err(err),
+            Err(err) if is_connection_error(&err) => {
+                warn!("Connection lost: {err:#}");
+                if deadline.is_some() {
+                    break;
+                }
+                tokio::select! {
+                    _ = &mut stop => break,
+                    reconnected = client.reconnect() => reconnected?,
+                }
+                continue;
+            }
+            Err(err) => warn!("Read failed: {err:#}"),
+        }
+        if deadline.is_some() {
+            break;
+        }
+        tokio::select! {
+            _ = &mut stop => break,
+            _ = tokio::time::sleep(jittered(options.interval, options.jitter_percent)) => {}
+        }
+    }
+    info!("Terminating");
+    disconnect_by(
+        client,
+        deadline.unwrap_or_else(|| Instant::now() + options.grace),
+    )
+    .await
+}
+
+struct WatchOptions {
+    register: u16,
+    expect: u16,
+    interval: Duration,
+    jitter_percent: u8,
+    grace: Duration,
+    exit_on_deviation: bool,
+}
+
+// Reads like `poll`, reconnecting per the policy, and stops the same way on
+// Ctrl-C or a termination request. Failing reads are logged but aren't
+// deviations.
+async fn watch_constant<T: Transport>(
+    mut client: ModbusForwarderClient<T>,
+    options: WatchOptions,
+) -> Result<()> {
+    let stop = stop_requested()?;
+    tokio::pin!(stop);
+    let WatchOptions {
+        register, expect, ..
+    } = options;
+    let (mut reads, mut deviations) = (0u64, 0u64);
+    let mut deadline = None;
+    loop {
+        let read = client.read_holding_registers(register, 1);
+        let Some(result) =
+            read_unless_stopped(read, stop.as_mut(), options.grace, &mut deadline).await
+        else {
+            break;
+        };
+        match result {
+            Ok(data) => {
+                reads += 1;
+                let failure = match data.first() {
+                    None => Some(anyhow!("read of {register} returned no registers")),
+                    Some(&value) if value != expect => {
+                        deviations += 1;
+                        error!(register, value, expect, deviations, "Register deviated");
+                        options.exit_on_deviation.then(|| {
+                            anyhow!("register {register} holds {value}, expected {expect}")
+                        })
+                    }
+                    Some(_) => None,
+                };
+                if let Some(err) = failure {
+                    let deadline = deadline.unwrap_or_else(|| Instant::now() + options.grace);
+                    disconnect_by(client, deadline).await?;
+                    return Err(err);
+                }
+            }
+            Err(err) if err.downcast_ref::<ByteCapReached>().is_some() => return Err(err),
+            Err(err) if is_connection_error(&err) => {
+                warn!("Connection lost: {err:#}");
+                if deadline.is_some() {
+                    break;
+                }
+                tokio::select! {
+                    _ = &mut stop => break,
+                    reconnected = client.reconnect() => reconnected?,
+                }
+                continue;
+            }
+            Err(err) => warn!("Read failed: {err:#}"),
+        }
+        if deadline.is_some() {
+            break;
+        }
+        tokio::select! {
+            _ = &mut stop => break,
+            _ = tokio::time::sleep(jittered(options.interval, options.jitter_percent)) => {}
+        }
+    }
+    info!(reads, deviations, "Terminating");
+    disconnect_by(
+        client,
+        deadline.unwrap_or_else(|| Instant::now() + options.grace),
+    )
+    .await?;
+    ensure!(
+        deviations == 0,
+        "register {register} deviated from {expect} in {deviations} of {reads} reads"
+    );
+    Ok(())
+}
+
+fn print_diff(
+    prefix: &str,
+    start: u16,
+    previous: Option<&[u16]>,
+    current: &[u16],
+    first: FirstPoll,
+) {
+    let address = |i: usize| usize::from(start) + i;
+    let Some(previous) = previous else {
+        if first == FirstPoll::All {
+            for (i, value) in current.iter().enumerate() {
+                println!("{prefix}{}: {value}", address(i));
+            }
+        }
+        return;
+    };
+
+    // A response of a different length shows the missing side as `-`
+    for i in 0..previous.len().max(current.len()) {
+        match (previous.get(i), current.get(i)) {
+            (Some(old), Some(new)) if old != new => {
+                println!("{prefix}{}: {old} -> {new}", address(i));
+            }
+            (Some(old), None) => println!("{prefix}{}: {old} -> -", address(i)),
+            (None, Some(new)) => println!("{prefix}{}: - -> {new}", address(i)),
+            _ => {}
+        }
+    }
+}
+
+// One block to read, or `None` for a blank or comment-only line
+fn parse_scan_line(line: &str) -> Result<Option<(ReadKind, u16, u16)>> {
+    let line = line.split('#').next().unwrap_or_default().trim();
+    if line.is_empty() {
+        return Ok(None);
+    }
+    let fields: Vec<&str> = line.split(',').map(str::trim).collect();
+    let [kind, start, count] = fields[..] else {
+        bail!("expected kind,start,count");
+    };
+    let kind = ReadKind::from_str(kind, true).map_err(|err| anyhow!(err))?;
+    let start = start
+        .parse()
+        .with_context(|| format!("bad start {start:?}"))?;
+    let count = count
+        .parse()
+        .with_context(|| format!("bad count {count:?}"))?;
+    ensure!(count > 0, "count must be at least 1");
+    Ok(Some((kind, start, count)))
+}
+
+// Reads each block of the list in turn, pausing `inter_request_delay`
+// between blocks as demo does between its requests.
+async fn scan<T: Transport>(
+    mut client: ModbusForwarderClient<T>,
+    list: &str,
+    sentinels: &Sentinels,
+    inter_request_delay: Duration,
+) -> Result<()> {
+    let mut blocks = Vec::new();
+    for (i, line) in list.lines().enumerate() {
+        match parse_scan_line(line) {
+            Ok(Some(block)) => blocks.push(block),
+            Ok(None) => {}
+            Err(err) => warn!("Skipping line {}: {err:#}", i + 1),
+        }
+    }
+
+    let mut failed = 0;
+    for (i, &(kind, start, count)) in blocks.iter().enumerate() {
+        if i > 0 && !inter_request_delay.is_zero() {
+            tokio::time::sleep(inter_request_delay).await;
+        }
+        match kind.read(&mut client, start, count).await {
+            Ok(data) => {
+                println!("{} {start}: {data:?}", kind.name());
+                if let Err(err) = sentinels.check(start, &data) {
+                    client.disconnect().await?;
+                    return Err(err);
+                }
+            }
+            Err(err) if err.downcast_ref::<ByteCapReached>().is_some() => return Err(err),
+            Err(err) if is_connection_error(&err) => {
+                client
+                    .reconnect()
+                    .await
+                    .with_context(|| format!("read at {start} lost the connection"))?;
+                eprintln!("read at {start} lost the connection, reconnected: {err:#}");
+                failed += 1;
+            }
+            Err(err) => {
+                eprintln!("read at {start} failed: {err:#}");
+                failed += 1;
+            }
+        }
+    }
+    client.disconnect().await?;
+
+    if failed > 0 {
+        bail!("{failed} of {} reads failed", blocks.len());
+    }
+    Ok(())
+}
+
+fn print_samples(prefix: &str, start: u16, data: &[u16], samples: &[usize]) {
+    for &i in samples {
+        match data.get(i) {
+            Some(value) => println!("{prefix}{}: {value}", usize::from(start) + i),
+            None => warn!(
+                "Sample offset {i} is outside the {}-value response",
+                data.len()
+            ),
+        }
+    }
+}
+
+// How long to wait for stray frames after the demo sequence
+const DRAIN_WAIT: Duration = Duration::from_millis(100);
+
+// Every read in the demo sequence starts here
+const DEMO_START: u16 = 0;
+
+struct DemoOptions {
+    // Pause between requests, not counted as part of any
+    inter_request_delay: Duration,
+    quiet: bool,
+    // Fail on frames nobody asked for instead of only warning
+    strict: bool,
+    sentinels: Sentinels,
+}
+
+async fn demo(mut client: ModbusForwarderClient, options: DemoOptions) -> Result<()> {
+    let DemoOptions {
+        inter_request_delay,
+        quiet,
+        strict,
+        sentinels,
+    } = options;
+    let sequence = [
+        (
+            "Holding Register",
+            Request::ReadHoldingRegisters(DEMO_START, 16),
+        ),
+        ("Coils", Request::ReadCoils(DEMO_START, 3)),
+        ("Discretes", Request::ReadDiscreteInputs(DEMO_START, 16)),
+        (
+            "Input Register",
+            Request::ReadInputRegisters(DEMO_START, 16),
+        ),
+    ];
+    let total = sequence.len();
+
+    // Report each request on its own so one failing read doesn't hide the
+    // rest; only losing the connection for good ends the run early
+    let mut failed = Vec::new();
+    for (i, (name, request)) in sequence.into_iter().enumerate() {
+        if i > 0 && !inter_request_delay.is_zero() {
+            tokio::time::sleep(inter_request_delay).await;
+        }
+        match client.request(request).await {
+            Ok(data) => {
+                if !quiet {
+                    println!("{name} Data is: {data:?}");
+                }
+                sentinels
+                    .check(DEMO_START, &data)
+                    .with_context(|| format!("{name} read"))?;
+            }
+            Err(err) if err.downcast_ref::<ByteCapReached>().is_some() => return Err(err),
+            Err(err) if is_connection_error(&err) => {
+                if let Err(reconnect_err) = client.reconnect().await {
+                    let context = format!("{name} read lost the connection ({reconnect_err})");
+                    return Err(err.context(context));
+                }
+                eprintln!("{name} read lost the connection, reconnected: {err:#}");
+                failed.push(name);
+            }
+            Err(err) => {
+                eprintln!("{name} read failed: {err:#}");
+                failed.push(name);
+            }
+        }
+    }
+    // Every request has had its answer, so anything still arriving is extra
+    let extra = client.drain_unexpected(DRAIN_WAIT).await?;
+    client.disconnect().await?;
+
+    if !quiet {
+        println!("{} of {total} requests succeeded", total - failed.len());
+    }
+    if !failed.is_empty() {
+        bail!("failed requests: {}", failed.join(", "));
+    }
+    if strict && !extra.is_empty() {
+        bail!(
+            "forwarder sent {} unexpected frames (sizes {extra:?})",
+            extra.len()
+        );
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+
+    // Answers bit reads padded out to a whole byte, as the forwarder does
+    fn padded_bits(request: Request<'static>) -> Option<Vec<u16>> {
+        match request {
+            Request::ReadCoils(_, count) | Request::ReadDiscreteInputs(_, count) => {
+                Some((0..count.next_multiple_of(8)).map(|i| i % 2).collect())
+            }
+            _ => None,
+        }
+    }
+
+    #[tokio::test]
+    async fn bit_reads_are_cut_to_the_count() {
+        let mut client = ModbusForwarderClient::builder("mock").build(mock::spawn(padded_bits));
+        for kind in [ReadKind::Coils, ReadKind::Discretes] {
+            assert_eq!(kind.read(&mut client, 0, 3).await.unwrap(), [0, 1, 0]);
+        }
+    }
+
+    #[tokio::test]
+    async fn scan_pauses_between_blocks() {
+        let sent = Arc::new(Mutex::new(Vec::new()));
+        let device = {
+            let sent = sent.clone();
+            move |request| {
+                sent.lock().unwrap().push(std::time::Instant::now());
+                selftest_device(request)
+            }
+        };
+        let client = ModbusForwarderClient::builder("mock").build(mock::spawn(device));
+        let sentinels = Sentinels {
+            values: Vec::new(),
+            range: None,
+        };
+        let delay = Duration::from_millis(20);
+        scan(
+            client,
+            "holding,0,2\ncoils,0,3\ninput,5,1\n",
+            &sentinels,
+            delay,
+        )
+        .await
+        .unwrap();
+
+        let sent = sent.lock().unwrap();
+        assert_eq!(sent.len(), 3);
+        for pair in sent.windows(2) {
+            assert!(pair[1] - pair[0] >= delay, "{:?}", pair[1] - pair[0]);
+        }
+    }
+
+    #[tokio::test]
+    async fn selftest_passes() {
+        selftest().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn watch_constant_fails_on_the_first_deviation() {
+        let sent = Arc::new(Mutex::new(Vec::new()));
+        let device = {
+            let sent = sent.clone();
+            move |request| {
+                sent.lock().unwrap().push(request);
+                Some(vec![7])
+            }
+        };
+        let client = ModbusForwarderClient::builder("mock").build(mock::spawn(device));
+        let options = WatchOptions {
+            register: 3,
+            expect: 5,
+            interval: Duration::from_millis(1),
+            jitter_percent: 0,
+            grace: Duration::from_secs(1),
+            exit_on_deviation: true,
+        };
+        let err = watch_constant(client, options).await.unwrap_err();
+        assert_eq!(err.to_string(), "register 3 holds 7, expected 5");
+        assert_eq!(*sent.lock().unwrap(), [Request::ReadHoldingRegisters(3, 1)]);
+    }
+}
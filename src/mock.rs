@@ -0,0 +1,56 @@
+// In-memory forwarder for driving the client without a real socket.
+
+use futures::{SinkExt, StreamExt};
+use tokio::io::DuplexStream;
+use tokio_modbus::Request;
+use tokio_util::codec::{FramedRead, FramedWrite};
+
+use crate::codec::{ModbusDataCodec, ModbusRequestCodec};
+
+// Room for plenty of frames in flight before either side has to wait
+const MOCK_BUFFER_SIZE: usize = 64 * 1024;
+
+/// The client's end of an in-memory connection to a mock forwarder.
+pub type MockTransport = DuplexStream;
+
+/// Spawns a mock forwarder on the current runtime and returns the client's
+/// end of the connection, for use with [`ClientBuilder::build`].
+///
+/// `handler` answers each request in turn. Returning `None` drops the
+/// connection, as a crashed forwarder would. The mock stops on Disconnect.
+///
+/// [`ClientBuilder::build`]: crate::ClientBuilder::build
+pub fn spawn<F>(mut handler: F) -> MockTransport
+where
+    F: FnMut(Request<'static>) -> Option<Vec<u16>> + Send + 'static,
+{
+    let (client, server) = tokio::io::duplex(MOCK_BUFFER_SIZE);
+    tokio::spawn(async move {
+        let (reader, writer) = tokio::io::split(server);
+        let mut requests = FramedRead::new(reader, ModbusRequestCodec::default());
+        let mut responses = FramedWrite::new(writer, ModbusDataCodec::default());
+        while let Some(Ok(request)) = requests.next().await {
+            if matches!(request, Request::Disconnect) {
+                break;
+            }
+            let Some(response) = handler(request) else {
+                break;
+            };
+            if responses.send(response).await.is_err() {
+                break;
+            }
+        }
+    });
+    client
+}
+
+/// Spawns a mock forwarder that answers requests with `responses` in order,
+/// whatever was asked, and drops the connection once they run out.
+pub fn scripted<I>(responses: I) -> MockTransport
+where
+    I: IntoIterator<Item = Vec<u16>>,
+    I::IntoIter: Send + 'static,
+{
+    let mut responses = responses.into_iter();
+    spawn(move |_| responses.next())
+}
@@ -0,0 +1,101 @@
+use std::io;
+use std::time::Duration;
+
+use serde::{Serialize, Serializer};
+use tokio_modbus::Request;
+
+use crate::error::{is_connection_error, ModbusException, UndecodableFrame};
+
+/// How a request ended, coarse enough to act on without parsing messages.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Status {
+    Ok,
+    /// An I/O timeout somewhere below the request.
+    Timeout,
+    /// The device answered with an exception response.
+    Exception,
+    /// The connection failed or was already down.
+    ConnectionError,
+    /// A response frame arrived but its payload couldn't be decoded.
+    DecodeError,
+    /// Anything else, such as a request refused before it was sent.
+    Error,
+}
+
+impl Status {
+    pub fn of<T>(result: &anyhow::Result<T>) -> Self {
+        match result {
+            Ok(_) => Status::Ok,
+            Err(err) => Self::of_error(err),
+        }
+    }
+
+    fn of_error(err: &anyhow::Error) -> Self {
+        let timed_out = err.chain().any(|cause| {
+            cause
+                .downcast_ref::<io::Error>()
+                .is_some_and(|err| err.kind() == io::ErrorKind::TimedOut)
+        });
+        if err.downcast_ref::<ModbusException>().is_some() {
+            Status::Exception
+        } else if timed_out {
+            Status::Timeout
+        } else if is_connection_error(err) {
+            Status::ConnectionError
+        } else if err.downcast_ref::<UndecodableFrame>().is_some()
+            || err.chain().any(|cause| cause.is::<bincode::Error>())
+        {
+            Status::DecodeError
+        } else {
+            Status::Error
+        }
+    }
+}
+
+/// The record of one request, as collected by a client built with
+/// [`ClientBuilder::record_outcomes`](crate::ClientBuilder::record_outcomes).
+/// Serializes to a flat JSON object.
+#[derive(Debug, Clone, Serialize)]
+pub struct RequestOutcome {
+    pub request: Request<'static>,
+    pub status: Status,
+    /// From sending to the final result, retries included. `None` for
+    /// requests sent in a batch, which overlap on the wire.
+    #[serde(rename = "latency_ms", serialize_with = "millis")]
+    pub latency: Option<Duration>,
+    /// The response data, when there is some.
+    pub values: Option<Vec<u16>>,
+    /// The error chain, when the request failed.
+    pub error: Option<String>,
+}
+
+impl RequestOutcome {
+    pub fn new(
+        request: Request<'static>,
+        result: &anyhow::Result<Vec<u16>>,
+        latency: Option<Duration>,
+    ) -> Self {
+        Self {
+            request,
+            status: Status::of(result),
+            latency,
+            values: result.as_ref().ok().cloned(),
+            error: result.as_ref().err().map(|err| format!("{err:#}")),
+        }
+    }
+
+    // For a response that arrived but failed to parse as what was asked
+    // for, once the outcome was already recorded as a success
+    pub(crate) fn fail(&mut self, err: &anyhow::Error) {
+        self.status = Status::of_error(err);
+        self.values = None;
+        self.error = Some(format!("{err:#}"));
+    }
+}
+
+fn millis<S: Serializer>(latency: &Option<Duration>, serializer: S) -> Result<S::Ok, S::Error> {
+    latency
+        .map(|latency| latency.as_secs_f64() * 1000.0)
+        .serialize(serializer)
+}
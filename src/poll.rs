@@ -0,0 +1,140 @@
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::Result;
+use futures::{stream, Stream};
+use tokio::time::{Interval, MissedTickBehavior};
+use tokio_modbus::Request;
+
+use crate::{ModbusForwarderClient, Transport};
+
+/// Stops a polling stream from outside, e.g. from a control-plane task.
+///
+/// Stopping takes effect on the stream's next tick. The handle can be cloned
+/// and outlive the stream; stopping an already dropped stream does nothing.
+#[derive(Debug, Clone, Default)]
+pub struct StopHandle {
+    stopped: Arc<AtomicBool>,
+}
+
+impl StopHandle {
+    pub fn stop(&self) {
+        self.stopped.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_stopped(&self) -> bool {
+        self.stopped.load(Ordering::Relaxed)
+    }
+}
+
+struct PollState<T: Transport> {
+    client: ModbusForwarderClient<T>,
+    request: Request<'static>,
+    interval: Interval,
+    stop: StopHandle,
+}
+
+struct ShutdownPollState<T: Transport> {
+    client: ModbusForwarderClient<T>,
+    request: Request<'static>,
+    interval: Interval,
+    shutdown: Pin<Box<dyn Future<Output = ()> + Send>>,
+}
+
+async fn disconnect_after_stop<T: Transport>(client: ModbusForwarderClient<T>) {
+    if let Err(err) = client.disconnect().await {
+        tracing::debug!("Disconnect after stop failed: {err:#}");
+    }
+}
+
+fn poll_interval(period: Duration) -> Interval {
+    let mut interval = tokio::time::interval(period);
+    interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    interval
+}
+
+impl<T: Transport> ModbusForwarderClient<T> {
+    /// Turns the client into a stream that sends `request` every `interval`
+    /// and yields each response.
+    ///
+    /// Once the returned handle is stopped, the stream sends Disconnect on
+    /// its next tick and ends. Request failures are yielded as errors without
+    /// ending the stream.
+    pub fn into_poll_stream(
+        self,
+        request: Request<'static>,
+        interval: Duration,
+    ) -> (impl Stream<Item = Result<Vec<u16>>>, StopHandle) {
+        let stop = StopHandle::default();
+        let state = PollState {
+            client: self,
+            request,
+            interval: poll_interval(interval),
+            stop: stop.clone(),
+        };
+
+        let stream = stream::unfold(state, |mut state| async move {
+            state.interval.tick().await;
+            if state.stop.is_stopped() {
+                disconnect_after_stop(state.client).await;
+                return None;
+            }
+            let result = state.client.request(state.request.clone()).await;
+            Some((result, state))
+        });
+        (stream, stop)
+    }
+
+    /// Like [`into_poll_stream`](Self::into_poll_stream), but stops as soon
+    /// as `shutdown` resolves, even while waiting for a tick or a response,
+    /// then sends Disconnect and ends. A request cut short this way is
+    /// abandoned, not yielded.
+    ///
+    /// `shutdown` is any future the caller controls, such as
+    /// `token.cancelled_owned()` for a tokio-util `CancellationToken` or a
+    /// oneshot receiver. The library installs no signal handlers of its own;
+    /// the binary's `poll` command stops on Ctrl-C or SIGTERM (Ctrl-Break on
+    /// Windows) with handlers of its own, and doesn't use this stream, so
+    /// the two never both fire. An embedding application that wants signals
+    /// to stop the stream should feed them into `shutdown` itself.
+    pub fn into_poll_stream_until(
+        self,
+        request: Request<'static>,
+        interval: Duration,
+        shutdown: impl Future<Output = ()> + Send + 'static,
+    ) -> impl Stream<Item = Result<Vec<u16>>> {
+        let state = ShutdownPollState {
+            client: self,
+            request,
+            interval: poll_interval(interval),
+            shutdown: Box::pin(shutdown),
+        };
+
+        stream::unfold(state, |mut state| async move {
+            let ShutdownPollState {
+                client,
+                request,
+                interval,
+                shutdown,
+            } = &mut state;
+            let result = tokio::select! {
+                biased;
+                _ = shutdown => None,
+                result = async {
+                    interval.tick().await;
+                    client.request(request.clone()).await
+                } => Some(result),
+            };
+            match result {
+                Some(result) => Some((result, state)),
+                None => {
+                    disconnect_after_stop(state.client).await;
+                    None
+                }
+            }
+        })
+    }
+}
@@ -0,0 +1,77 @@
+// SOCKS5 tunnelling, for forwarders only reachable through a bastion. Once
+// the proxy has connected us the tunnel is a plain TCP stream, so only
+// establishing the connection differs.
+
+use std::fmt;
+use std::str::FromStr;
+
+use anyhow::{anyhow, ensure};
+
+/// A SOCKS5 proxy, parsed from `socks5://[user:password@]host:port`.
+///
+/// Tunnelling needs the `socks5` feature; without it, connecting through a
+/// proxy fails with `Unsupported`.
+#[derive(Clone, PartialEq, Eq)]
+pub struct Socks5Proxy {
+    pub addr: String,
+    pub credentials: Option<(String, String)>,
+}
+
+// Keeps the password out of logs
+impl fmt::Debug for Socks5Proxy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Socks5Proxy")
+            .field("addr", &self.addr)
+            .field("user", &self.credentials.as_ref().map(|(user, _)| user))
+            .finish()
+    }
+}
+
+impl FromStr for Socks5Proxy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let rest = s
+            .strip_prefix("socks5://")
+            .ok_or_else(|| anyhow!("expected a socks5:// proxy URL, got {s:?}"))?;
+        // Split at the last `@` so a password may contain one
+        let (credentials, addr) = match rest.rsplit_once('@') {
+            Some((userinfo, addr)) => {
+                let (user, password) = userinfo
+                    .split_once(':')
+                    .ok_or_else(|| anyhow!("proxy credentials must be user:password"))?;
+                (Some((user.to_owned(), password.to_owned())), addr)
+            }
+            None => (None, rest),
+        };
+        let addr = addr.trim_end_matches('/');
+        ensure!(
+            addr.rsplit_once(':')
+                .is_some_and(|(host, port)| !host.is_empty() && port.parse::<u16>().is_ok()),
+            "proxy address must be host:port, got {addr:?}"
+        );
+        Ok(Socks5Proxy {
+            addr: addr.to_owned(),
+            credentials,
+        })
+    }
+}
+
+#[cfg(feature = "socks5")]
+impl Socks5Proxy {
+    /// Opens a TCP connection to `target` through the proxy.
+    pub async fn connect(&self, target: &str) -> std::io::Result<tokio::net::TcpStream> {
+        use tokio_socks::tcp::Socks5Stream;
+
+        let stream = match &self.credentials {
+            Some((user, password)) => {
+                Socks5Stream::connect_with_password(self.addr.as_str(), target, user, password)
+                    .await
+            }
+            None => Socks5Stream::connect(self.addr.as_str(), target).await,
+        };
+        stream
+            .map(Socks5Stream::into_inner)
+            .map_err(std::io::Error::other)
+    }
+}
@@ -0,0 +1,38 @@
+use std::time::Duration;
+
+use tokio::time::Instant;
+
+/// Token bucket capping how many requests a client sends per second.
+///
+/// The bucket holds up to one second's worth of requests, so a client that
+/// has been idle may send that many back to back before being held to the
+/// steady rate.
+#[derive(Debug, Clone)]
+pub struct RateLimiter {
+    per_second: f64,
+    tokens: f64,
+    refilled: Instant,
+}
+
+impl RateLimiter {
+    pub fn new(per_second: u32) -> Self {
+        let per_second = f64::from(per_second.max(1));
+        Self {
+            per_second,
+            tokens: per_second,
+            refilled: Instant::now(),
+        }
+    }
+
+    /// Takes a token for one request and returns how long to wait before
+    /// sending it, if at all. The token is spent either way, so callers
+    /// must wait out the delay rather than ask again.
+    pub fn reserve(&mut self) -> Option<Duration> {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.refilled).as_secs_f64();
+        self.refilled = now;
+        self.tokens = (self.tokens + elapsed * self.per_second).min(self.per_second) - 1.0;
+        // A negative balance is the backlog of reservations still to wait for
+        (self.tokens < 0.0).then(|| Duration::from_secs_f64(-self.tokens / self.per_second))
+    }
+}
@@ -0,0 +1,68 @@
+use anyhow::Result;
+use tokio_modbus::Request;
+
+use crate::client::check_count;
+use crate::{ModbusForwarderClient, Transport};
+
+/// What a snapshot reads: a `(start, count)` block per register type, or
+/// `None` to leave that type out.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SnapshotSpec {
+    pub holding: Option<(u16, u16)>,
+    pub input: Option<(u16, u16)>,
+    pub coils: Option<(u16, u16)>,
+    pub discretes: Option<(u16, u16)>,
+}
+
+/// The result of [`ModbusForwarderClient::read_snapshot`]. A type the spec
+/// left out is `None`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Snapshot {
+    pub holding: Option<Vec<u16>>,
+    pub input: Option<Vec<u16>>,
+    pub coils: Option<Vec<u16>>,
+    pub discretes: Option<Vec<u16>>,
+}
+
+impl<T: Transport> ModbusForwarderClient<T> {
+    /// Reads every block in `spec` in one pipelined batch. Fails if any of
+    /// the reads fails, naming which one.
+    pub async fn read_snapshot(&mut self, spec: &SnapshotSpec) -> Result<Snapshot> {
+        let blocks = [spec.holding, spec.input, spec.coils, spec.discretes];
+        for (_, count) in blocks.into_iter().flatten() {
+            check_count(count)?;
+        }
+        let requests = [
+            spec.holding
+                .map(|(start, count)| Request::ReadHoldingRegisters(start, count)),
+            spec.input
+                .map(|(start, count)| Request::ReadInputRegisters(start, count)),
+            spec.coils
+                .map(|(start, count)| Request::ReadCoils(start, count)),
+            spec.discretes
+                .map(|(start, count)| Request::ReadDiscreteInputs(start, count)),
+        ];
+
+        let mut responses = self
+            .send_batch(requests.into_iter().flatten().collect())
+            .await
+            .into_iter();
+        // One response per block the spec asked for, in the order above
+        let mut take = |block: Option<(u16, u16)>, name: &str| {
+            block
+                .map(|_| {
+                    responses
+                        .next()
+                        .expect("one response per request")
+                        .map_err(|err| err.context(format!("snapshot read of {name} failed")))
+                })
+                .transpose()
+        };
+        Ok(Snapshot {
+            holding: take(spec.holding, "holding registers")?,
+            input: take(spec.input, "input registers")?,
+            coils: take(spec.coils, "coils")?,
+            discretes: take(spec.discretes, "discrete inputs")?,
+        })
+    }
+}
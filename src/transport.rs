@@ -0,0 +1,109 @@
+use std::future::{self, Future};
+use std::io;
+use std::net::SocketAddr;
+
+use tokio::io::{AsyncRead, AsyncWrite, DuplexStream, ReadHalf, WriteHalf};
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::net::TcpStream;
+
+use crate::proxy::Socks5Proxy;
+
+/// A byte stream the client can run its framing over.
+///
+/// TCP is the normal transport; in-memory duplex streams let the client
+/// logic be driven without a socket (see [`crate::mock`]).
+pub trait Transport: Sized + Send + 'static {
+    type Reader: AsyncRead + Send + Unpin + 'static;
+    type Writer: AsyncWrite + Send + Unpin + 'static;
+
+    fn into_split(self) -> (Self::Reader, Self::Writer);
+
+    /// The resolved address at the other end, for transports that have one.
+    fn peer_addr(&self) -> Option<SocketAddr> {
+        None
+    }
+
+    /// Opens a new connection to `addr`, both initially and to reconnect.
+    /// Transports that can't be re-established fail with `Unsupported`.
+    fn connect(addr: &str) -> impl Future<Output = io::Result<Self>> + Send;
+
+    /// Opens a new connection to `addr` tunnelled through `proxy`. Only TCP
+    /// can be tunnelled, and only with the `socks5` feature.
+    fn connect_via(
+        proxy: &Socks5Proxy,
+        addr: &str,
+    ) -> impl Future<Output = io::Result<Self>> + Send {
+        let _ = (proxy, addr);
+        future::ready(Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "this transport cannot be tunnelled through a SOCKS5 proxy",
+        )))
+    }
+}
+
+impl Transport for TcpStream {
+    type Reader = OwnedReadHalf;
+    type Writer = OwnedWriteHalf;
+
+    fn into_split(self) -> (Self::Reader, Self::Writer) {
+        TcpStream::into_split(self)
+    }
+
+    fn peer_addr(&self) -> Option<SocketAddr> {
+        TcpStream::peer_addr(self).ok()
+    }
+
+    fn connect(addr: &str) -> impl Future<Output = io::Result<Self>> + Send {
+        TcpStream::connect(addr.to_owned())
+    }
+
+    #[cfg(feature = "socks5")]
+    fn connect_via(
+        proxy: &Socks5Proxy,
+        addr: &str,
+    ) -> impl Future<Output = io::Result<Self>> + Send {
+        let (proxy, addr) = (proxy.clone(), addr.to_owned());
+        async move { proxy.connect(&addr).await }
+    }
+}
+
+/// Any bidirectional byte stream the caller has already set up, such as a
+/// TLS-wrapped or otherwise upgraded socket, used as a [`Transport`]. The
+/// client didn't open it and can't open another, so it can't reconnect.
+#[derive(Debug)]
+pub struct ProvidedStream<S>(pub S);
+
+impl<S> Transport for ProvidedStream<S>
+where
+    S: AsyncRead + AsyncWrite + Send + Unpin + 'static,
+{
+    type Reader = ReadHalf<S>;
+    type Writer = WriteHalf<S>;
+
+    fn into_split(self) -> (Self::Reader, Self::Writer) {
+        tokio::io::split(self.0)
+    }
+
+    fn connect(_addr: &str) -> impl Future<Output = io::Result<Self>> + Send {
+        future::ready(Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "streams handed to the client cannot reconnect",
+        )))
+    }
+}
+
+impl Transport for DuplexStream {
+    type Reader = ReadHalf<DuplexStream>;
+    type Writer = WriteHalf<DuplexStream>;
+
+    fn into_split(self) -> (Self::Reader, Self::Writer) {
+        tokio::io::split(self)
+    }
+
+    fn connect(_addr: &str) -> impl Future<Output = io::Result<Self>> + Send {
+        future::ready(Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "in-memory transports cannot reconnect",
+        )))
+    }
+}